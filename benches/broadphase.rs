@@ -0,0 +1,74 @@
+//! Compares the broadphase backends against the naive all-pairs loop, on a
+//! uniform gas and on one with wildly mixed radii. Run with
+//! `cargo bench --bench broadphase`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use physim::cgmath::Vector3;
+use physim::phys::broadphase::{self, Aabb};
+
+/// Particles spread through a cube at roughly constant density, of radius 1 or,
+/// if `mixed`, with one in a hundred ten times larger.
+fn gas(count: usize, seed: u32, mixed: bool) -> Vec<Aabb> {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+
+    let side = (count as f32 * 50.0).cbrt();
+    (0..count)
+        .enumerate()
+        .map(|(k, _)| {
+            let radius = if mixed && k % 100 == 0 { 10.0 } else { 1.0 };
+            Aabb::around_sphere(Vector3::new(next(), next(), next()) * side, radius)
+        })
+        .collect()
+}
+
+/// Average time per call over enough calls to fill about half a second.
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    let mut calls = 0;
+    while calls < 3 || start.elapsed() < Duration::from_millis(500) {
+        f();
+        calls += 1;
+    }
+
+    start.elapsed() / calls
+}
+
+fn main() {
+    let backends = ["brute", "grid", "sweep-x", "tree"];
+
+    for mixed in [false, true] {
+        println!("{} radii", if mixed { "Mixed" } else { "Equal" });
+        print!("{:>8}", "count");
+        for name in backends {
+            print!(" {:>14}", name);
+        }
+        println!(" {:>8}", "pairs");
+
+        for count in [1_000, 10_000, 100_000] {
+            let boxes = gas(count, 1, mixed);
+            let mut pairs = Vec::new();
+
+            print!("{:>8}", count);
+            for name in backends {
+                // The naive loop takes seconds per step past a few tens of thousands
+                if name == "brute" && count > 10_000 {
+                    print!(" {:>14}", "-");
+                    continue;
+                }
+
+                let Some(mut broadphase) = broadphase::by_name(name) else {
+                    panic!("Unknown broadphase {}", name);
+                };
+                let elapsed = time(|| broadphase.find_pairs(black_box(&boxes), &mut pairs));
+                print!(" {:>14}", format!("{:?}", elapsed));
+            }
+            println!(" {:>8}", pairs.len());
+        }
+    }
+}
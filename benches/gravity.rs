@@ -0,0 +1,68 @@
+//! Compares Barnes-Hut gravity at a few opening angles against direct
+//! summation on a uniform cloud. Run with `cargo bench --bench gravity`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use physim::part;
+use physim::phys::Particle;
+use physim::phys::gravity::{NBodyGravity, Octree};
+
+/// Particles of mass 1 scattered uniformly through a cube.
+fn cloud(count: usize, seed: u32) -> Vec<Particle> {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+
+    let side = (count as f32 * 50.0).cbrt();
+    (0..count)
+        .map(|_| part![0.0, next() * side, next() * side, next() * side; 1.0; 1.0])
+        .collect()
+}
+
+/// Average time per call over enough calls to fill about half a second.
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    let mut calls = 0;
+    while calls < 3 || start.elapsed() < Duration::from_millis(500) {
+        f();
+        calls += 1;
+    }
+
+    start.elapsed() / calls
+}
+
+fn main() {
+    let thetas = [0.0, 0.3, 0.5, 1.0];
+
+    print!("{:>8}", "count");
+    for theta in thetas {
+        print!(" {:>14}", format!("theta {}", theta));
+    }
+    println!();
+
+    for count in [1_000, 10_000, 100_000] {
+        let mut particles = cloud(count, 1);
+        let mut tree = Octree::new();
+
+        print!("{:>8}", count);
+        for theta in thetas {
+            // Direct summation takes tens of seconds per step at this size
+            if theta == 0.0 && count > 10_000 {
+                print!(" {:>14}", "-");
+                continue;
+            }
+
+            let gravity = NBodyGravity {
+                g: 1.0,
+                softening: 1.0,
+                theta,
+            };
+            let elapsed = time(|| gravity.accelerate_with(black_box(&mut particles), &mut tree));
+            print!(" {:>14}", format!("{:?}", elapsed));
+        }
+        println!();
+    }
+}
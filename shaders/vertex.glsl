@@ -2,7 +2,7 @@
 
 // Base mesh attributes
 in vec3 pos;
-in vec3 base_color; // Base mesh color (if needed)
+in vec3 color; // Base mesh color, tinted by the instance color
 
 // Instance attributes (per particle)
 in vec3 i_pos;
@@ -16,6 +16,6 @@ void main() {
     // Transform the base vertex position for this instance
     vec3 world_pos = pos * i_radius + i_pos;
 
-    v_color = i_color;
+    v_color = i_color * color;
     gl_Position = matrix * vec4(world_pos, 1.0);
 }
@@ -0,0 +1,34 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use physim::cli::HeadlessCli;
+use physim::headless;
+
+fn main() {
+    let running = Arc::new(AtomicBool::new(true));
+    let run_setter = running.clone();
+
+    ctrlc::set_handler(move || {
+        run_setter.store(false, Ordering::SeqCst);
+    })
+    .expect("Error Setting Exit handler");
+
+    let args = HeadlessCli::parse_and_validate();
+
+    let (scene, step_config) = match args.sim.load() {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}: {}", args.sim.scene.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut world = scene.build();
+    match headless::run(&mut world, &args.run.config(step_config.dt), &running) {
+        Ok(summary) => eprintln!("{}", summary),
+        Err(e) => {
+            eprintln!("Headless run failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
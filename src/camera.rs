@@ -1,6 +1,4 @@
-use cgmath::{
-    EuclideanSpace, Matrix, Matrix4, Point3, Quaternion, Rad, Rotation, Vector3, perspective,
-};
+use cgmath::{EuclideanSpace, Matrix4, Point3, Quaternion, Rad, Rotation, Vector3, perspective};
 
 use crate::mat::Mat4;
 
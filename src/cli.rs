@@ -0,0 +1,224 @@
+use std::path::PathBuf;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+
+use crate::headless::{HeadlessConfig, StopCondition};
+use crate::scene::{Scene, SceneError};
+use crate::threading::StepConfig;
+
+/// Special-relativistic particle simulator.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    #[command(flatten)]
+    pub sim: SimArgs,
+
+    /// Window width in pixels
+    #[arg(long, default_value_t = 1280, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: u32,
+
+    /// Window height in pixels
+    #[arg(long, default_value_t = 720, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: u32,
+
+    /// Wait for vertical sync between frames
+    #[arg(long)]
+    pub vsync: bool,
+
+    /// Vertical field of view in degrees
+    #[arg(long, default_value_t = 60.0)]
+    pub fov: f32,
+
+    /// Near clipping plane distance
+    #[arg(long, default_value_t = 0.1)]
+    pub near: f32,
+
+    /// Far clipping plane distance
+    #[arg(long, default_value_t = 10000.0)]
+    pub far: f32,
+
+    /// Directory containing vertex.glsl and fragment.glsl
+    #[arg(long, default_value = "shaders")]
+    pub shaders: PathBuf,
+
+    /// Run the simulation without opening a window
+    #[arg(long)]
+    pub headless: bool,
+
+    #[command(flatten)]
+    pub run: HeadlessArgs,
+}
+
+/// Command line of the windowless runner.
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Runs a physim scene without a window and writes the results as CSV"
+)]
+pub struct HeadlessCli {
+    #[command(flatten)]
+    pub sim: SimArgs,
+
+    #[command(flatten)]
+    pub run: HeadlessArgs,
+}
+
+/// Options shared by every way of running a scene.
+#[derive(clap::Args, Debug)]
+pub struct SimArgs {
+    /// Scene file describing the initial conditions
+    #[arg(default_value = "scenes/default.toml")]
+    pub scene: PathBuf,
+
+    /// Fixed timestep in seconds, overriding the scene's
+    #[arg(long)]
+    pub dt: Option<f32>,
+
+    /// Seed for randomly generated particles, overriding the scene's
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Barnes-Hut opening angle for N-body gravity, overriding the scene's
+    #[arg(long)]
+    pub theta: Option<f32>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct HeadlessArgs {
+    /// Number of ticks to simulate in headless mode
+    #[arg(long, default_value_t = 1000)]
+    pub steps: u64,
+
+    /// Stop the headless run once simulated time reaches this many seconds
+    #[arg(long)]
+    pub until_time: Option<f32>,
+
+    /// Stop the headless run once a particle is this far from the origin
+    #[arg(long)]
+    pub until_escape: Option<f32>,
+
+    /// Write particle states every N steps instead of only at the end
+    #[arg(long, default_value_t = 0)]
+    pub record_every: u64,
+
+    /// CSV file the headless run writes its results to, instead of stdout
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+impl SimArgs {
+    /// Loads the scene and its step settings with the command line overrides applied.
+    pub fn load(&self) -> Result<(Scene, StepConfig), SceneError> {
+        let mut scene = Scene::load(&self.scene)?;
+        if let Some(seed) = self.seed {
+            scene.world.seed = seed;
+        }
+        if let Some(theta) = self.theta {
+            match scene.n_body.as_mut() {
+                Some(n_body) => n_body.get_mut().theta = theta,
+                None => eprintln!(
+                    "warning: --theta is ignored, {} has no [n_body] table",
+                    self.scene.display()
+                ),
+            }
+        }
+
+        let mut step_config = scene.step_config();
+        if let Some(dt) = self.dt {
+            step_config.dt = dt;
+        }
+
+        Ok((scene, step_config))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(dt) = self.dt
+            && !(dt.is_finite() && dt > 0.0)
+        {
+            return Err(format!("--dt must be positive and finite, got {}", dt));
+        }
+        if let Some(theta) = self.theta
+            && (theta.is_nan() || theta < 0.0)
+        {
+            return Err(format!("--theta must be zero or positive, got {}", theta));
+        }
+
+        Ok(())
+    }
+}
+
+impl HeadlessArgs {
+    pub fn config(&self, dt: f32) -> HeadlessConfig {
+        HeadlessConfig {
+            steps: self.steps,
+            dt,
+            until: self.stop_conditions(),
+            record_every: self.record_every,
+            output: self.output.clone(),
+        }
+    }
+
+    pub fn stop_conditions(&self) -> Vec<StopCondition> {
+        let time = self.until_time.map(StopCondition::Time);
+        let escape = self.until_escape.map(StopCondition::Escape);
+
+        time.into_iter().chain(escape).collect()
+    }
+
+    fn is_used(&self) -> bool {
+        self.until_time.is_some() || self.until_escape.is_some() || self.output.is_some()
+    }
+}
+
+impl Args {
+    /// Parses the command line, exiting with a usage message on invalid input.
+    pub fn parse_and_validate() -> Self {
+        let args = Self::parse();
+
+        if let Err(message) = args.validate() {
+            Self::command()
+                .error(ErrorKind::ValueValidation, message)
+                .exit();
+        }
+
+        args
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.sim.validate()?;
+
+        if !(1.0..180.0).contains(&self.fov) {
+            return Err(format!("--fov must be between 1 and 180, got {}", self.fov));
+        }
+        if self.near.is_nan() || self.near <= 0.0 {
+            return Err(format!("--near must be positive, got {}", self.near));
+        }
+        if self.far.is_nan() || self.far <= self.near {
+            return Err(format!(
+                "--far ({}) must be greater than --near ({})",
+                self.far, self.near
+            ));
+        }
+        if !self.headless && self.run.is_used() {
+            return Err("--until-time, --until-escape and --output need --headless".to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+impl HeadlessCli {
+    /// Parses the command line, exiting with a usage message on invalid input.
+    pub fn parse_and_validate() -> Self {
+        let args = Self::parse();
+
+        if let Err(message) = args.sim.validate() {
+            Self::command()
+                .error(ErrorKind::ValueValidation, message)
+                .exit();
+        }
+
+        args
+    }
+}
@@ -1,18 +1,129 @@
 use glium::{
     DrawParameters, IndexBuffer, Program, Surface, VertexBuffer, glutin::surface::WindowSurface,
-    uniform, vertex::PerInstance,
+    index::PrimitiveType, uniform, vertex::PerInstance,
 };
 
-use crate::{mat::Mat4, vx::Vx};
+use physim::{
+    mat::Mat4,
+    mesh,
+    phys::Plane,
+    vx::{InstanceData, Vx},
+};
 
 #[macro_export]
 macro_rules! glsl {
     ($shader:literal) => {
-        match fs::read_to_string("shaders/".to_owned() + $shader + ".glsl") {
+        glsl!("shaders", $shader)
+    };
+
+    ($dir:expr, $shader:literal) => {{
+        let path = std::path::Path::new($dir).join($shader.to_owned() + ".glsl");
+        match fs::read_to_string(&path) {
             Ok(s) => s,
-            Err(e) => panic!("Unable to read file: shaders/{:}.glsl: {:?}", $shader, e),
+            Err(e) => panic!("Unable to read file: {}: {:?}", path.display(), e),
         }
-    };
+    }};
+}
+
+/// GPU buffers for the scene's planes and boxes. They never move, so this is
+/// uploaded once and drawn every frame alongside the particles.
+pub struct PlaneMesh {
+    vertices: VertexBuffer<Vx>,
+    triangles: Option<IndexBuffer<u16>>,
+    lines: Option<IndexBuffer<u16>>,
+    instance: VertexBuffer<InstanceData>,
+}
+
+impl PlaneMesh {
+    pub fn new(display: &glium::Display<WindowSurface>, planes: &[Plane]) -> Option<Self> {
+        if planes.is_empty() {
+            return None;
+        }
+
+        let (vertices, triangles, lines) = mesh::generate_plane_mesh(planes);
+
+        let Ok(vertices) = VertexBuffer::new(display, &vertices) else {
+            panic!("Failed to create vertex buffer for planes");
+        };
+        let Ok(instance) = VertexBuffer::new(display, &[InstanceData::identity()]) else {
+            panic!("Failed to create instance buffer for planes");
+        };
+
+        let index_buffer = |primitive, indices: Vec<u16>| {
+            if indices.is_empty() {
+                return None;
+            }
+            match IndexBuffer::new(display, primitive, &indices) {
+                Ok(buffer) => Some(buffer),
+                Err(e) => panic!("Failed to create index buffer for planes: {:?}", e),
+            }
+        };
+
+        Some(PlaneMesh {
+            vertices,
+            triangles: index_buffer(PrimitiveType::TrianglesList, triangles),
+            lines: index_buffer(PrimitiveType::LinesList, lines),
+            instance,
+        })
+    }
+}
+
+/// GPU buffers for cloth and soft bodies. Their shape changes every frame, so
+/// the buffers are dynamic and rewritten in place while the sizes stay the
+/// same.
+pub struct SurfaceMesh {
+    vertices: Option<VertexBuffer<Vx>>,
+    triangles: Option<IndexBuffer<u32>>,
+    instance: VertexBuffer<InstanceData>,
+}
+
+impl SurfaceMesh {
+    pub fn new(display: &glium::Display<WindowSurface>) -> Self {
+        let Ok(instance) = VertexBuffer::new(display, &[InstanceData::identity()]) else {
+            panic!("Failed to create instance buffer for surfaces");
+        };
+
+        SurfaceMesh {
+            vertices: None,
+            triangles: None,
+            instance,
+        }
+    }
+
+    /// Uploads this frame's surfaces, from `mesh::generate_surface_mesh`.
+    pub fn update(
+        &mut self,
+        display: &glium::Display<WindowSurface>,
+        vertices: &[Vx],
+        triangles: &[u32],
+    ) {
+        if triangles.is_empty() {
+            self.triangles = None;
+            return;
+        }
+
+        match &self.vertices {
+            Some(buffer) if buffer.len() == vertices.len() => buffer.write(vertices),
+            _ => {
+                let Ok(buffer) = VertexBuffer::dynamic(display, vertices) else {
+                    panic!("Failed to create vertex buffer for surfaces");
+                };
+                self.vertices = Some(buffer);
+            }
+        }
+
+        match &self.triangles {
+            Some(buffer) if buffer.len() == triangles.len() => buffer.write(triangles),
+            _ => {
+                let Ok(buffer) =
+                    IndexBuffer::dynamic(display, PrimitiveType::TrianglesList, triangles)
+                else {
+                    panic!("Failed to create index buffer for surfaces");
+                };
+                self.triangles = Some(buffer);
+            }
+        }
+    }
 }
 
 pub fn draw_shape(
@@ -20,6 +131,8 @@ pub fn draw_shape(
     indices: &IndexBuffer<u16>,
     program: &Program,
     vi_buf: (&VertexBuffer<Vx>, PerInstance),
+    planes: Option<&PlaneMesh>,
+    surfaces: &SurfaceMesh,
     matrix: &Mat4,
     params: &DrawParameters,
 ) {
@@ -42,6 +155,46 @@ pub fn draw_shape(
         }
     };
 
+    if let Some(planes) = planes {
+        for indices in planes.triangles.iter().chain(&planes.lines) {
+            let Ok(instance) = planes.instance.per_instance() else {
+                panic!("Error creating plane instance buffer");
+            };
+
+            match target.draw(
+                (&planes.vertices, instance),
+                indices,
+                program,
+                &uniform! {
+                    matrix: *matrix
+                },
+                params,
+            ) {
+                Ok(_) => {}
+                Err(e) => println!("Error drawing planes: {:?}", e),
+            };
+        }
+    }
+
+    if let (Some(vertices), Some(triangles)) = (&surfaces.vertices, &surfaces.triangles) {
+        let Ok(instance) = surfaces.instance.per_instance() else {
+            panic!("Error creating surface instance buffer");
+        };
+
+        match target.draw(
+            (vertices, instance),
+            triangles,
+            program,
+            &uniform! {
+                matrix: *matrix
+            },
+            params,
+        ) {
+            Ok(_) => {}
+            Err(e) => println!("Error drawing surfaces: {:?}", e),
+        };
+    }
+
     match target.finish() {
         Ok(_) => {}
         Err(e) => println!("Failed to draw: {:?}", e),
@@ -1,13 +1,10 @@
-use core::panic;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 
 use crate::input;
-use crate::phys::InstanceData;
-use crate::threading::PhysicsMessage;
-use cgmath::Rotation;
-use crossbeam::channel::Receiver;
+use crate::input::SimControl;
+use crossbeam::channel::Sender;
 use glium::VertexBuffer;
 use glium::glutin::surface::WindowSurface;
 use glium::vertex::PerInstance;
@@ -16,17 +13,23 @@ use glium::winit::event::{Event, WindowEvent};
 use glium::winit::event_loop::ActiveEventLoop;
 use glium::winit::keyboard::KeyCode;
 use glium::winit::window::Window;
+use physim::camera::CamParams;
+use physim::cgmath::Rotation;
+use physim::mesh;
+use physim::threading::{PhysicsCommand, Snapshot, SnapshotSlot};
+use physim::vx::{self, InstanceData, Vx};
 
-use crate::CamParams;
-
-pub fn handle<F: FnOnce(PerInstance)>(
+pub fn handle<F: FnOnce(PerInstance, &[Vx], &[u32])>(
     l_t: &mut Instant,
     event: Event<()>,
     window_target: &ActiveEventLoop,
     window: &Window,
     display: &glium::Display<WindowSurface>,
     cam: &mut CamParams,
-    physics_rx: &Receiver<PhysicsMessage>,
+    physics_slot: &SnapshotSlot,
+    snapshot: &mut Option<Snapshot>,
+    commands: &Sender<PhysicsCommand>,
+    sim: &mut SimControl,
     running: &Arc<AtomicBool>,
     draw_cb: F,
 ) {
@@ -79,25 +82,47 @@ pub fn handle<F: FnOnce(PerInstance)>(
                 if event.physical_key == KeyCode::Escape {
                     running.store(false, Ordering::SeqCst);
                 }
+                if event.state.is_pressed()
+                    && !event.repeat
+                    && let Some(command) = input::command_handle(event.physical_key, sim)
+                {
+                    match commands.send(command) {
+                        Ok(_) => {}
+                        Err(e) => println!("Failed to send command to physics thread: {:?}", e),
+                    };
+                }
             }
             WindowEvent::RedrawRequested => {
                 // t = start.elapsed().as_secs_f32();
                 let dt = l_t.elapsed().as_secs_f32();
                 *l_t = Instant::now();
 
-                println!("{:.2} Fps", 1.0 / dt);
+                let stats = physics_slot.stats();
+                println!(
+                    "{:.2} Fps | physics frames produced: {}, dropped: {}",
+                    1.0 / dt,
+                    stats.produced,
+                    stats.dropped
+                );
 
                 // (position, orientation) = rotate_around_origin_xz_dt(dt, position, orientation);
 
-                // TODO: Don't block
-                let Ok(message) = physics_rx.recv() else {
-                    panic!("Failed to communicate with physics thread");
-                };
+                // Keep drawing the last snapshot until the physics thread has a newer one
+                if let Some(latest) = physics_slot.take() {
+                    *snapshot = Some(latest);
+                }
 
-                let instance_data = match message {
-                    PhysicsMessage::InstanceData(data) => data,
+                let Some(snapshot) = snapshot else {
+                    return;
                 };
 
+                let mut instance_data: Vec<InstanceData> = snapshot.interpolated();
+
+                // Cloth and soft bodies are drawn as meshes over their particles
+                let (surface_vertices, surface_triangles) =
+                    mesh::generate_surface_mesh(&instance_data, &snapshot.triangles);
+                vx::hide_surface_particles(&mut instance_data, &snapshot.triangles);
+
                 // let instance_data: Vec<InstanceData> = Vec::new();
 
                 // let instance_data = world.get_instance_data();
@@ -111,7 +136,7 @@ pub fn handle<F: FnOnce(PerInstance)>(
                     panic!("Error creating instance buffer");
                 };
 
-                draw_cb(instance_buffer);
+                draw_cb(instance_buffer, &surface_vertices, &surface_triangles);
             }
 
             _ => (),
@@ -0,0 +1,143 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use cgmath::InnerSpace;
+
+use crate::phys::PhysicsWorld;
+
+/// Ways a headless run can end before it has used up its steps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopCondition {
+    /// Simulated time has reached this many seconds.
+    Time(f32),
+    /// Some particle is further than this from the origin.
+    Escape(f32),
+}
+
+impl StopCondition {
+    fn is_met(&self, world: &PhysicsWorld) -> bool {
+        match *self {
+            StopCondition::Time(t) => world.time() >= t,
+            StopCondition::Escape(d) => world
+                .particles
+                .iter()
+                .any(|p| p.spatial_position().magnitude2() > d * d),
+        }
+    }
+}
+
+impl Display for StopCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopCondition::Time(t) => write!(f, "reached t = {} s", t),
+            StopCondition::Escape(d) => write!(f, "a particle escaped past {} m", d),
+        }
+    }
+}
+
+pub struct HeadlessConfig {
+    /// Most ticks to simulate.
+    pub steps: u64,
+    pub dt: f32,
+    pub until: Vec<StopCondition>,
+    /// Write particle states every this many steps, 0 for only the final state.
+    pub record_every: u64,
+    /// CSV destination, stdout if not set.
+    pub output: Option<PathBuf>,
+}
+
+pub struct RunSummary {
+    pub steps: u64,
+    pub time: f32,
+    pub stopped_by: Option<StopCondition>,
+    pub initial_energy: f32,
+    pub final_energy: f32,
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Simulated {} steps, t = {} s", self.steps, self.time)?;
+        if let Some(condition) = self.stopped_by {
+            write!(f, ", stopped because {}", condition)?;
+        }
+        write!(
+            f,
+            ". Energy {} J -> {} J",
+            self.initial_energy, self.final_energy
+        )
+    }
+}
+
+/// Steps `world` without any window or GL context, writing particle states as
+/// CSV (`step,t,id,x,y,z,vx,vy,vz,mass,radius,tau`).
+pub fn run(
+    world: &mut PhysicsWorld,
+    config: &HeadlessConfig,
+    running: &AtomicBool,
+) -> io::Result<RunSummary> {
+    let mut out: Box<dyn Write> = match &config.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    writeln!(out, "step,t,id,x,y,z,vx,vy,vz,mass,radius,tau")?;
+
+    let initial_energy = world.energy();
+    let mut steps = 0;
+
+    let (stopped_by, recorded) = loop {
+        let recorded = config.record_every > 0 && steps % config.record_every == 0;
+        if recorded {
+            write_states(&mut out, steps, world)?;
+        }
+
+        let stopped_by = config.until.iter().copied().find(|c| c.is_met(world));
+        if stopped_by.is_some() || steps >= config.steps || !running.load(Ordering::SeqCst) {
+            break (stopped_by, recorded);
+        }
+
+        world.update(config.dt);
+        steps += 1;
+    };
+
+    if !recorded {
+        write_states(&mut out, steps, world)?;
+    }
+    out.flush()?;
+
+    Ok(RunSummary {
+        steps,
+        time: world.time(),
+        stopped_by,
+        initial_energy,
+        final_energy: world.energy(),
+    })
+}
+
+fn write_states(out: &mut dyn Write, step: u64, world: &PhysicsWorld) -> io::Result<()> {
+    for (id, p) in world.particles.iter().enumerate() {
+        let x = p.spatial_position();
+        let v = p.three_velocity();
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            step,
+            world.time(),
+            id,
+            x.x,
+            x.y,
+            x.z,
+            v.x,
+            v.y,
+            v.z,
+            p.mass,
+            p.radius,
+            p.tau
+        )?;
+    }
+
+    Ok(())
+}
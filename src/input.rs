@@ -1,8 +1,24 @@
-use cgmath::{Deg, InnerSpace, Quaternion, Rad, Rotation, Rotation3, Vector3};
 use glium::winit::keyboard::PhysicalKey::{Code, Unidentified};
 use glium::winit::keyboard::{KeyCode, PhysicalKey};
+use physim::cgmath::{Deg, InnerSpace, Quaternion, Rad, Rotation, Rotation3, Vector3};
 
-use crate::camera::CamParams;
+use physim::camera::CamParams;
+use physim::threading::PhysicsCommand;
+
+/// Renderer-side copy of the physics run state, so a key can toggle it.
+pub struct SimControl {
+    pub paused: bool,
+    pub time_scale: f32,
+}
+
+impl Default for SimControl {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            time_scale: 1.0,
+        }
+    }
+}
 
 pub fn rotate_cam(d_pos: (f64, f64), cam: &mut CamParams) {
     let d_pos = (d_pos.0 as f32, d_pos.1 as f32);
@@ -36,3 +52,41 @@ pub fn key_handle<F: FnOnce(Vector3<f32>)>(key: PhysicalKey, cb: F) {
         Unidentified(_) => {}
     }
 }
+
+pub fn command_handle(key: PhysicalKey, sim: &mut SimControl) -> Option<PhysicsCommand> {
+    match key {
+        Code(code) => match code {
+            KeyCode::Space => {
+                sim.paused = !sim.paused;
+                if sim.paused {
+                    Some(PhysicsCommand::Pause)
+                } else {
+                    Some(PhysicsCommand::Resume)
+                }
+            }
+            KeyCode::Period => {
+                sim.paused = true;
+                Some(PhysicsCommand::Step(1))
+            }
+            KeyCode::Slash => {
+                sim.paused = true;
+                Some(PhysicsCommand::Step(10))
+            }
+            KeyCode::Minus => {
+                sim.time_scale *= 0.5;
+                Some(PhysicsCommand::SetTimeScale(sim.time_scale))
+            }
+            KeyCode::Equal => {
+                sim.time_scale *= 2.0;
+                Some(PhysicsCommand::SetTimeScale(sim.time_scale))
+            }
+            KeyCode::Digit0 => {
+                sim.time_scale = 1.0;
+                Some(PhysicsCommand::SetTimeScale(sim.time_scale))
+            }
+            KeyCode::KeyR => Some(PhysicsCommand::Reset),
+            _ => None,
+        },
+        Unidentified(_) => None,
+    }
+}
@@ -0,0 +1,17 @@
+//! Physics core of physim: relativistic particles, scenes and the headless
+//! and real-time runners. The glium vertex and uniform impls used by the
+//! `physim` renderer are behind the `render` feature, so the physics can be
+//! used without pulling in glium.
+
+pub use cgmath;
+
+pub mod camera;
+pub mod cli;
+pub mod geo;
+pub mod headless;
+pub mod mat;
+pub mod mesh;
+pub mod phys;
+pub mod scene;
+pub mod threading;
+pub mod vx;
@@ -4,29 +4,25 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 
-use cgmath::Rotation3;
-use cgmath::{Deg, Quaternion, Vector3};
 use crossbeam::channel::unbounded;
 use glium::IndexBuffer;
 use glium::Surface;
 use glium::VertexBuffer;
 use glium::winit::event_loop::ControlFlow;
 use glium::winit::event_loop::EventLoop;
+use physim::camera::{self, CamParams};
+use physim::cgmath::Rotation3;
+use physim::cgmath::{Deg, Quaternion, Vector3};
+use physim::cli::Args;
+use physim::headless;
+use physim::mesh;
+use physim::threading::{self, PhysicsCommand, SnapshotSlot};
 
-mod camera;
 mod drawing;
 mod events;
-mod geo;
 mod input;
-mod mat;
-mod mesh;
-mod phys;
-mod threading;
-mod vx;
 
-use camera::CamParams;
-use phys::PhysicsWorld;
-use threading::PhysicsMessage;
+use input::SimControl;
 
 #[allow(deprecated)]
 fn main() {
@@ -38,31 +34,61 @@ fn main() {
     })
     .expect("Error Setting Exit handler");
 
+    let args = Args::parse_and_validate();
+
+    let (scene, step_config) = match args.sim.load() {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}: {}", args.sim.scene.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    // Nothing below this branch is needed without a window, so headless runs
+    // never create an event loop or GL context.
+    if args.headless {
+        let mut world = scene.build();
+        match headless::run(&mut world, &args.run.config(step_config.dt), &running) {
+            Ok(summary) => eprintln!("{}", summary),
+            Err(e) => {
+                eprintln!("Headless run failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let event_loop = EventLoop::builder().build().unwrap();
     let (window, display) = glium::backend::glutin::SimpleWindowBuilder::new()
-        .with_vsync(false)
+        .with_inner_size(args.width, args.height)
+        .with_vsync(args.vsync)
         .build(&event_loop);
 
     let _ = window.set_cursor_grab(glium::winit::window::CursorGrabMode::Confined);
     window.set_cursor_visible(false);
 
-    let (tx, rx) = unbounded::<PhysicsMessage>();
+    let physics_slot = Arc::new(SnapshotSlot::default());
+    let mut snapshot = None;
+    let (command_tx, command_rx) = unbounded::<PhysicsCommand>();
+    let mut sim = SimControl::default();
 
-    let vertex_shader = glsl!("vertex");
-    let fragment_shader = glsl!("fragment");
+    println!(
+        "Controls: WASD move, Space pause/resume, . step, / step 10, - and = time scale, 0 real time, R reset, Esc quit"
+    );
 
-    let world = PhysicsWorld::new();
+    let vertex_shader = glsl!(&args.shaders, "vertex");
+    let fragment_shader = glsl!(&args.shaders, "fragment");
 
-    let (base_vertices, base_indices) = world.get_base_mesh();
+    let (base_vertices, base_indices) = mesh::generate_icosphere_mesh(2);
 
     // Create base mesh buffers (do this once)
-    let Ok(v_buf) = VertexBuffer::new(&display, base_vertices) else {
+    let Ok(v_buf) = VertexBuffer::new(&display, &base_vertices) else {
         panic!("Failed to create vertex buffer for base sphere mesh");
     };
     let Ok(i_buf) = IndexBuffer::new(
         &display,
         glium::index::PrimitiveType::TrianglesList,
-        base_indices,
+        &base_indices,
     ) else {
         panic!("Failed to create index buffer for base sphere mesh");
     };
@@ -85,6 +111,9 @@ fn main() {
         ..Default::default()
     };
 
+    let plane_mesh = drawing::PlaneMesh::new(&display, &scene.planes());
+    let mut surface_mesh = drawing::SurfaceMesh::new(&display);
+
     let Ok(program) = glium::Program::from_source(&display, &vertex_shader, &fragment_shader, None)
     else {
         panic!("Unable to parse shaders");
@@ -93,7 +122,8 @@ fn main() {
     let position = Vector3::new(0.0, 0.0, 0.0);
     let orientation = Quaternion::from_angle_y(Deg(-90.0)); // Looking backward
 
-    let fov = 60.0;
+    let fov = args.fov;
+    let (near, far) = (args.near, args.far);
 
     let size = window.inner_size();
 
@@ -107,14 +137,15 @@ fn main() {
         ar: ar,
     };
 
-    let mut matrix = camera::camera_matrix(cam.pos, cam.ori, cam.fov, cam.ar, 0.1, 100.0);
+    let mut matrix = camera::camera_matrix(cam.pos, cam.ori, cam.fov, cam.ar, near, far);
 
     let mut l_t = Instant::now();
 
     let physics_run = running.clone();
+    let physics_out = physics_slot.clone();
 
     let physics_thread = std::thread::spawn(move || {
-        threading::phys_start(physics_run, tx);
+        threading::phys_start(physics_run, physics_out, command_rx, scene, step_config);
     });
 
     let _ = event_loop.run(move |event, window_target| {
@@ -131,21 +162,27 @@ fn main() {
             &window,
             &display,
             &mut cam,
-            &rx,
+            &physics_slot,
+            &mut snapshot,
+            &command_tx,
+            &mut sim,
             &running,
-            |ins_buffer| {
+            |ins_buffer, surface_vertices, surface_triangles| {
+                surface_mesh.update(&display, surface_vertices, surface_triangles);
                 drawing::draw_shape(
                     &display,
                     &i_buf,
                     &program,
                     (&v_buf, ins_buffer),
+                    plane_mesh.as_ref(),
+                    &surface_mesh,
                     &matrix,
                     &draw_params,
                 );
             },
         );
 
-        matrix = camera::camera_matrix(cam.pos, cam.ori, cam.fov, cam.ar, 0.1, 10000.0);
+        matrix = camera::camera_matrix(cam.pos, cam.ori, cam.fov, cam.ar, near, far);
     });
     match physics_thread.join() {
         Ok(_) => {}
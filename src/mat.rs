@@ -1,3 +1,4 @@
+#[cfg(feature = "render")]
 use glium::uniforms::{AsUniformValue, UniformValue};
 
 #[derive(Debug, Copy, Clone)]
@@ -5,6 +6,7 @@ pub struct Mat4 {
     pub data: [[f32; 4]; 4], // column-major
 }
 
+#[cfg(feature = "render")]
 impl AsUniformValue for Mat4 {
     fn as_uniform_value(&self) -> UniformValue {
         UniformValue::Mat4(self.data)
@@ -24,6 +26,6 @@ macro_rules! mat {
             }
         }
 
-        Mat4 { data }
+        $crate::mat::Mat4 { data }
     }};
 }
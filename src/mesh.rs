@@ -1,6 +1,7 @@
+use crate::phys::Plane;
 use crate::vx;
-use crate::vx::Vx;
-use cgmath::{Array, Quaternion, Vector3, Zero}; // Assuming you're using `glam` for vectors/quaternions
+use crate::vx::{InstanceData, Vx};
+use cgmath::{InnerSpace, Vector3};
 use std::f32::consts::PI;
 
 pub fn generate_unit_sphere_mesh(lat_segments: u32, lon_segments: u32) -> (Vec<Vx>, Vec<u16>) {
@@ -158,3 +159,83 @@ fn get_or_create_midpoint(
 
     new_index
 }
+
+/// World-space geometry for a set of planes, as `(vertices, triangles, lines)`.
+/// Flat planes are filled quads and boxes are wireframes so the particles
+/// inside them stay visible.
+pub fn generate_plane_mesh(planes: &[Plane]) -> (Vec<Vx>, Vec<u16>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    let mut lines = Vec::new();
+
+    for plane in planes {
+        let base = vertices.len() as u16;
+        let half = plane.size / 2.0;
+        let [r, g, b] = plane.color;
+
+        // Corner k is on the positive side of axis a when bit a of k is set
+        for k in 0..8 {
+            let side = |a: usize| if k & (1 << a) != 0 { half[a] } else { -half[a] };
+            let p = plane.center + cgmath::Vector3::new(side(0), side(1), side(2));
+            vertices.push(vx![p.x, p.y, p.z => r, g, b]);
+        }
+
+        if plane.flat {
+            let mut axes = (0..3).filter(|&a| half[a] != 0.0);
+            let (Some(a1), Some(a2)) = (axes.next(), axes.next()) else {
+                continue;
+            };
+            let (c1, c2) = (1 << a1, 1 << a2);
+
+            triangles.extend([0, c1, c1 | c2, 0, c1 | c2, c2].map(|k| base + k));
+        } else {
+            for k in 0..8 {
+                for a in 0..3 {
+                    if k & (1 << a) == 0 {
+                        lines.extend([base + k, base + (k | (1 << a))]);
+                    }
+                }
+            }
+        }
+    }
+
+    (vertices, triangles, lines)
+}
+
+/// World-space geometry for cloth and soft bodies, rebuilt every frame as
+/// their particles move. `triangles` index `instances` three at a time, and
+/// each vertex takes its particle's colour, shaded by the surface normal
+/// there so folds stay visible.
+pub fn generate_surface_mesh(instances: &[InstanceData], triangles: &[u32]) -> (Vec<Vx>, Vec<u32>) {
+    let position = |i: u32| Vector3::from(instances[i as usize].i_pos);
+
+    // Area weighted vertex normals
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); instances.len()];
+    for t in triangles.chunks(3) {
+        let (a, b, c) = (position(t[0]), position(t[1]), position(t[2]));
+        let normal = (b - a).cross(c - a);
+        for &i in t {
+            normals[i as usize] += normal;
+        }
+    }
+
+    let light = Vector3::new(0.3, 1.0, 0.5).normalize();
+    let vertices = instances
+        .iter()
+        .zip(normals)
+        .map(|(instance, normal)| {
+            let [x, y, z] = instance.i_pos;
+            let [r, g, b] = instance.i_color;
+            // Both sides of a sheet are lit alike
+            let length = normal.magnitude();
+            let shade = if length > 0.0 {
+                0.35 + 0.65 * (normal.dot(light) / length).abs()
+            } else {
+                1.0
+            };
+            vx![x, y, z => r * shade, g * shade, b * shade]
+        })
+        .collect();
+
+    (vertices, triangles.to_vec())
+}
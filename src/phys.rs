@@ -1,20 +1,58 @@
+use std::collections::HashSet;
 use std::fmt::Display;
 
-use crate::mesh::generate_icosphere_mesh;
-use crate::vx::Vx;
-use cgmath::{ElementWise, InnerSpace, Vector3, Vector4, Zero};
-use glium::implement_vertex;
+pub mod broadphase;
+pub mod constraint;
+pub mod coulomb;
+pub mod em;
+pub mod force;
+pub mod gravity;
+pub mod integrator;
+pub mod material;
+pub mod potential;
+pub mod softbody;
+pub mod sph;
+
+use crate::phys::broadphase::{Aabb, Broadphase, SpatialHash};
+use crate::phys::constraint::Constraint;
+use crate::phys::coulomb::Coulomb;
+use crate::phys::em::{EmField, lorentz_force};
+use crate::phys::force::ForceField;
+use crate::phys::gravity::{NBodyGravity, Octree};
+use crate::phys::integrator::{Integrator, SemiImplicitEuler};
+use crate::phys::material::{ContactMaterial, Material, MaterialId};
+use crate::phys::potential::{PairPotentials, SpeciesId};
+use crate::phys::softbody::Surface;
+use crate::phys::sph::Sph;
+use cgmath::{InnerSpace, Vector3, Vector4, Zero};
+use serde::Deserialize;
 
 #[derive(Clone, Debug)]
 pub struct Particle {
     pub position: Vector4<f32>,
     pub velocity: Vector4<f32>,
     pub v: Vector4<f32>,
-    pub acceleration: Vector4<f32>, // Can be calculated from forces
+    /// Force per unit rest mass, d(gamma * v)/dt. Rebuilt from the forces
+    /// every time the integrator asks, so set `base_acceleration` instead.
+    pub acceleration: Vector4<f32>,
+    /// Acceleration of this particle alone, added to the world's forces. The
+    /// time component is unused.
+    pub base_acceleration: Vector4<f32>,
     pub mass: f32,
     pub radius: f32,     // For visualization and simple collision
     pub color: [f32; 3], // For visualization
     pub tau: f32,
+    /// Angular velocity in rad/s. Only friction changes it.
+    pub omega: Vector3<f32>,
+    pub material: MaterialId,
+    /// Electric charge in coulombs.
+    pub charge: f32,
+    /// Electric and magnetic field at the particle when forces were last
+    /// evaluated, for integrators that treat them specially.
+    pub e_field: Vector3<f32>,
+    pub b_field: Vector3<f32>,
+    /// Picks the pair potentials this particle feels.
+    pub species: SpeciesId,
 }
 
 #[derive(Clone, Debug)]
@@ -22,6 +60,75 @@ pub struct Plane {
     pub verts: Vec<Vector3<f32>>,
     pub flat: bool,
     pub color: [f32; 3],
+    pub center: Vector3<f32>,
+    /// Full extent along each axis, with one zero extent for a flat plane.
+    pub size: Vector3<f32>,
+    pub material: MaterialId,
+}
+
+impl Plane {
+    /// The finite rectangles particles collide with, as `(center, half extents)`
+    /// with one zero half extent. A box is made of its six faces.
+    pub fn faces(&self) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+        let half = self.size / 2.0;
+        if self.flat {
+            return vec![(self.center, half)];
+        }
+
+        let mut faces = Vec::with_capacity(6);
+        for axis in 0..3 {
+            let mut face_half = half;
+            face_half[axis] = 0.0;
+
+            for side in [1.0, -1.0] {
+                let mut offset = Vector3::zero();
+                offset[axis] = side * half[axis];
+                faces.push((self.center + offset, face_half));
+            }
+        }
+
+        faces
+    }
+}
+
+/// Axis a face is perpendicular to, the one its half extents are zero along.
+fn face_axis(half: Vector3<f32>) -> usize {
+    (0..3).find(|&a| half[a] == 0.0).unwrap_or(1)
+}
+
+/// Contact between a sphere and a two-sided finite face, as the unit normal
+/// pointing from the face toward the sphere and how deep the sphere overlaps.
+fn face_contact(
+    center: Vector3<f32>,
+    half: Vector3<f32>,
+    position: Vector3<f32>,
+    radius: f32,
+) -> Option<(Vector3<f32>, f32)> {
+    let d = position - center;
+    let closest = Vector3::new(
+        d.x.clamp(-half.x, half.x),
+        d.y.clamp(-half.y, half.y),
+        d.z.clamp(-half.z, half.z),
+    );
+
+    let delta = d - closest;
+    let dist_sq = delta.magnitude2();
+    if dist_sq > radius * radius {
+        return None;
+    }
+
+    let dist = dist_sq.sqrt();
+    let normal = if dist > 1e-6 {
+        delta / dist
+    } else {
+        // The centre is on the face itself, so push out along the face normal
+        let axis = face_axis(half);
+        let mut normal = Vector3::zero();
+        normal[axis] = if d[axis] < 0.0 { -1.0 } else { 1.0 };
+        normal
+    };
+
+    Some((normal, radius - dist))
 }
 
 pub fn get_plane_verts(
@@ -86,6 +193,8 @@ pub fn get_plane_verts(
     (verts, flat)
 }
 
+/// Builds a `Plane` from its center, size and colour, then optionally the
+/// `MaterialId` it collides with, 0 by default.
 #[macro_export]
 macro_rules! plane {
     (
@@ -93,16 +202,28 @@ macro_rules! plane {
         $xl:expr, $yl:expr, $zl:expr;
         $rgb:expr
     ) => {
-        match get_plane_verts(
-            $x as f32, $y as f32, $z as f32, $xl as f32, $yl as f32, $zl as f32,
-        ) {
-            (verts, flat) => Plane {
-                verts,
-                flat,
-                color: $rgb,
-            },
-        }
+        $crate::plane![$x, $y, $z; $xl, $yl, $zl; $rgb; 0]
     };
+
+    (
+        $x:expr, $y:expr, $z:expr ; // Center
+        $xl:expr, $yl:expr, $zl:expr;
+        $rgb:expr ; $material:expr  // Index into the world's materials
+    ) => {{
+        let center = $crate::cgmath::Vector3::new($x as f32, $y as f32, $z as f32);
+        let size = $crate::cgmath::Vector3::new($xl as f32, $yl as f32, $zl as f32);
+        let (verts, flat) =
+            $crate::phys::get_plane_verts(center.x, center.y, center.z, size.x, size.y, size.z);
+
+        $crate::phys::Plane {
+            verts,
+            flat,
+            color: $rgb,
+            center,
+            size,
+            material: $material,
+        }
+    }};
 }
 
 impl Display for Particle {
@@ -137,21 +258,67 @@ impl Particle {
             velocity: u,
             v,
             acceleration: Vector4::zero(),
+            base_acceleration: Vector4::zero(),
             mass,
             radius,
             color,
             tau,
+            omega: Vector3::zero(),
+            material: 0,
+            charge: 0.0,
+            e_field: Vector3::zero(),
+            b_field: Vector3::zero(),
+            species: 0,
         }
     }
-}
 
-#[derive(Clone, Debug, Copy)]
-pub struct InstanceData {
-    i_pos: [f32; 3],
-    i_color: [f32; 3],
-    i_radius: f32,
+    pub fn spatial_position(&self) -> Vector3<f32> {
+        spatial(self.position)
+    }
+
+    pub fn set_spatial_position(&mut self, x: Vector3<f32>) {
+        self.position = Vector4::new(self.position[0], x.x, x.y, x.z);
+    }
+
+    /// Coordinate 3-velocity dx/dt.
+    pub fn three_velocity(&self) -> Vector3<f32> {
+        spatial(self.v)
+    }
+
+    pub fn set_three_velocity(&mut self, v: Vector3<f32>) {
+        self.v = Vector4::new(C, v.x, v.y, v.z);
+        self.velocity = normalize_4v(self.v);
+    }
+
+    /// Spatial part of the 4-velocity, gamma * v (dx/dtau).
+    pub fn proper_velocity(&self) -> Vector3<f32> {
+        spatial(self.velocity)
+    }
+
+    /// Sets the 4-velocity from its spatial part. Unlike the 3-velocity this can
+    /// grow without bound, so integrating it never pushes a particle past C.
+    pub fn set_proper_velocity(&mut self, u: Vector3<f32>) {
+        let gamma = gamma_from_proper(u);
+        self.velocity = Vector4::new(gamma * C, u.x, u.y, u.z);
+        self.v = Vector4::new(C, u.x / gamma, u.y / gamma, u.z / gamma);
+    }
+
+    pub fn gamma(&self) -> f32 {
+        self.velocity[0] / C
+    }
+
+    /// Relativistic kinetic energy (gamma - 1) m C^2, written so it does not
+    /// cancel to zero in f32 at everyday speeds.
+    pub fn kinetic_energy(&self) -> f32 {
+        let u = self.proper_velocity();
+        self.mass * u.magnitude2() / (gamma_from_proper(u) + 1.0)
+    }
+
+    /// Moment of inertia of a uniform solid sphere.
+    pub fn moment_of_inertia(&self) -> f32 {
+        0.4 * self.mass * self.radius * self.radius
+    }
 }
-implement_vertex!(InstanceData, i_pos, i_color, i_radius);
 
 pub const C: f32 = 299792458.0;
 
@@ -162,9 +329,9 @@ macro_rules! part {
         $m:expr ;                 // Mandatory mass
         $ra:expr                  // Mandatory radius
     ) => {
-        Particle::new(
-            Vector4::new($t as f32, $x as f32, $y as f32, $z as f32),
-            Vector4::new(C, 0.0, 0.0, 0.0),
+        $crate::phys::Particle::new(
+            $crate::cgmath::Vector4::new($t as f32, $x as f32, $y as f32, $z as f32),
+            $crate::cgmath::Vector4::new($crate::phys::C, 0.0, 0.0, 0.0),
             ($m as f32),
             ($ra as f32),
             [1.0, 1.0, 1.0],
@@ -176,9 +343,9 @@ macro_rules! part {
     $vx:expr, $vy:expr, $vz:expr ;
     $m:expr ; $ra:expr ;
     $r:expr, $g:expr, $b:expr) => {
-        Particle::new(
-            Vector4::new($t as f32, $x as f32, $y as f32, $z as f32),
-            Vector4::new(C, $vx as f32, $vy as f32, $vz as f32),
+        $crate::phys::Particle::new(
+            $crate::cgmath::Vector4::new($t as f32, $x as f32, $y as f32, $z as f32),
+            $crate::cgmath::Vector4::new($crate::phys::C, $vx as f32, $vy as f32, $vz as f32),
             ($m as f32),
             ($ra as f32),
             [$r as f32, $g as f32, $b as f32],
@@ -187,32 +354,116 @@ macro_rules! part {
     };
 }
 
+/// How overlapping spheres are pushed apart after contacts are resolved.
+#[derive(Clone, Copy, Debug)]
+pub struct PositionCorrection {
+    /// Overlap left alone, as a fraction of the smaller radius, so resting
+    /// contacts don't jitter.
+    pub slop: f32,
+    /// Fraction of the remaining overlap removed each step.
+    pub baumgarte: f32,
+}
+
+impl Default for PositionCorrection {
+    fn default() -> Self {
+        PositionCorrection {
+            slop: 0.01,
+            baumgarte: 0.2,
+        }
+    }
+}
+
+/// What a collision does with the kinetic energy it takes out of the
+/// particles' relative motion.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionMode {
+    /// Bounce with the materials' restitution. Energy lost to `e < 1` is gone.
+    #[default]
+    Bounce,
+    /// Bounce with the materials' restitution, turning the lost energy into
+    /// rest mass so the full 4-momentum is conserved.
+    Inelastic,
+    /// Fuse touching particles into one carrying their total 4-momentum.
+    Merge,
+}
+
 pub struct PhysicsWorld {
     pub particles: Vec<Particle>,
     pub planes: Vec<Plane>,
+    /// Indexed by `Particle::material` and `Plane::material`. The first entry
+    /// is what particles and planes get unless told otherwise, and also what
+    /// an id past the end falls back to.
+    pub materials: Vec<Material>,
+    /// Uniform acceleration on every particle, the time component unused.
     pub gravity: Vector4<f32>,
+    /// External forces added to the acceleration of every particle.
+    pub force_fields: Vec<Box<dyn ForceField>>,
+    /// Attraction between the particles themselves, off when `None`.
+    pub n_body: Option<NBodyGravity>,
+    /// External electromagnetic field acting on charged particles.
+    pub em_field: Option<Box<dyn EmField>>,
+    /// Electrostatic attraction and repulsion between charged particles.
+    pub coulomb: Option<Coulomb>,
+    /// Short-range forces between particles by species, for molecular
+    /// dynamics.
+    pub pair_potentials: Option<PairPotentials>,
+    /// Springs, rods and ropes between particles. Linked particles don't
+    /// collide with each other.
+    pub constraints: Vec<Constraint>,
+    /// Solver passes over the rods and ropes each step.
+    pub constraint_iterations: usize,
+    /// Meshes drawn over cloth and soft bodies instead of their particles.
+    pub surfaces: Vec<Surface>,
+    /// Treats every particle as a parcel of fluid. Particles then push each
+    /// other through pressure instead of colliding, but still bounce off
+    /// planes.
+    pub sph: Option<Sph>,
+    pub correction: PositionCorrection,
+    /// Sub-step each update to the earliest impact so fast particles can't
+    /// pass through each other or through planes.
+    pub ccd: bool,
+    pub collision_mode: CollisionMode,
     t: f32,
-    base_sphere_vertices: Vec<Vx>,
-    base_sphere_indices: Vec<u16>,
-    sphere_vertex_count: u32,
-    sphere_index_count: u32,
+    integrator: Box<dyn Integrator>,
+    broadphase: Box<dyn Broadphase>,
+    // Reused between steps to avoid reallocating for every update
+    boxes: Vec<Aabb>,
+    pairs: Vec<(usize, usize)>,
+    octree: Octree,
+}
+
+impl Default for PhysicsWorld {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PhysicsWorld {
     pub fn new() -> Self {
-        let (verts, inds) = generate_icosphere_mesh(2);
-        let vert_count = verts.len() as u32;
-        let index_count = inds.len() as u32;
-
         Self {
             particles: Vec::new(),
             planes: Vec::new(),
+            materials: vec![Material::default()],
             gravity: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            force_fields: Vec::new(),
+            n_body: None,
+            em_field: None,
+            coulomb: None,
+            pair_potentials: None,
+            constraints: Vec::new(),
+            constraint_iterations: 10,
+            surfaces: Vec::new(),
+            sph: None,
+            correction: PositionCorrection::default(),
+            ccd: false,
+            collision_mode: CollisionMode::Bounce,
             t: 0.0,
-            base_sphere_vertices: verts,
-            base_sphere_indices: inds,
-            sphere_vertex_count: vert_count,
-            sphere_index_count: index_count,
+            integrator: Box::new(SemiImplicitEuler),
+            broadphase: Box::new(SpatialHash::new()),
+            boxes: Vec::new(),
+            pairs: Vec::new(),
+            octree: Octree::new(),
         }
     }
 
@@ -220,21 +471,77 @@ impl PhysicsWorld {
         self.particles.push(particle);
     }
 
-    // New method to get instance data instead of all vertices
-    pub fn get_instance_data(&self) -> Vec<InstanceData> {
-        self.particles
+    pub fn add_force_field(&mut self, field: impl ForceField + 'static) {
+        self.force_fields.push(Box::new(field));
+    }
+
+    pub fn set_integrator(&mut self, integrator: Box<dyn Integrator>) {
+        self.integrator = integrator;
+    }
+
+    pub fn integrator(&self) -> &dyn Integrator {
+        self.integrator.as_ref()
+    }
+
+    pub fn set_broadphase(&mut self, broadphase: Box<dyn Broadphase>) {
+        self.broadphase = broadphase;
+    }
+
+    pub fn broadphase(&self) -> &dyn Broadphase {
+        self.broadphase.as_ref()
+    }
+
+    /// Total kinetic energy, for comparing drift between integrators.
+    pub fn kinetic_energy(&self) -> f32 {
+        self.particles.iter().map(Particle::kinetic_energy).sum()
+    }
+
+    /// Potential energy of the forces between particles.
+    pub fn potential_energy(&self) -> f32 {
+        let gravity = match &self.n_body {
+            Some(n_body) => n_body.potential_energy(&self.particles),
+            None => 0.0,
+        };
+        let electric = match &self.coulomb {
+            Some(coulomb) => coulomb.potential_energy(&self.particles),
+            None => 0.0,
+        };
+
+        let pairs = match &self.pair_potentials {
+            Some(potentials) => potentials.potential_energy(&self.particles),
+            None => 0.0,
+        };
+
+        let springs: f32 = self
+            .constraints
             .iter()
-            .map(|p| InstanceData {
-                i_pos: [p.position[1], p.position[2], p.position[3]],
-                i_color: p.color,
-                i_radius: p.radius,
-            })
-            .collect()
+            .map(|c| c.potential_energy(&self.particles))
+            .sum();
+
+        // Height in the uniform field
+        let g = spatial(self.gravity);
+        let uniform: f32 = self
+            .particles
+            .iter()
+            .map(|p| -p.mass * g.dot(p.spatial_position()))
+            .sum();
+
+        let fluid = match &self.sph {
+            Some(sph) => sph.potential_energy(&self.particles),
+            None => 0.0,
+        };
+
+        gravity + electric + pairs + springs + fluid + uniform
+    }
+
+    /// Kinetic plus potential energy.
+    pub fn energy(&self) -> f32 {
+        self.kinetic_energy() + self.potential_energy()
     }
 
-    // Keep base mesh separate
-    pub fn get_base_mesh(&self) -> (&Vec<Vx>, &Vec<u16>) {
-        (&self.base_sphere_vertices, &self.base_sphere_indices)
+    /// Simulated coordinate time in seconds.
+    pub fn time(&self) -> f32 {
+        self.t
     }
 
     /// The core update function for the simulation
@@ -256,13 +563,233 @@ impl PhysicsWorld {
     //     // You would also handle inter-particle collisions here
     // }
     pub fn update(&mut self, dt: f32) {
-        let num_particles = self.particles.len();
+        if !self.ccd {
+            self.step(dt);
+            return;
+        }
 
-        // Phase 1: Update 3-velocity (`p.v`) based on acceleration for all particles.
-        // This calculates the velocity *before* any collisions in this timestep.
-        for p in self.particles.iter_mut() {
-            // Apply acceleration to 3-velocity using cgmath vector operations
-            p.v += p.acceleration * dt;
+        // Advance impact by impact, each sub-step ending with a pair just
+        // touching so its contact is resolved before it can pass through.
+        let mut remaining = dt;
+        for _ in 0..MAX_CCD_SUBSTEPS {
+            match self.time_of_impact(remaining) {
+                Some(toi) if toi < remaining => {
+                    self.step(toi);
+                    remaining -= toi;
+                }
+                _ => break,
+            }
+        }
+
+        self.step(remaining);
+    }
+
+    /// Time until the first particle-particle or particle-plane impact,
+    /// assuming straight-line motion, if it happens within `horizon`.
+    /// Pairs already in contact are left to the regular contact pass.
+    fn time_of_impact(&mut self, horizon: f32) -> Option<f32> {
+        // Boxes around the whole path of each particle over the horizon
+        self.boxes.clear();
+        self.boxes.extend(self.particles.iter().map(|p| {
+            let x = p.spatial_position();
+            let end = x + p.three_velocity() * horizon;
+            Aabb::around_sphere(x, p.radius).union(&Aabb::around_sphere(end, p.radius))
+        }));
+        self.broadphase.find_pairs(&self.boxes, &mut self.pairs);
+        unlink_pairs(&mut self.pairs, &self.constraints);
+        if self.sph.is_some() {
+            self.pairs.clear();
+        }
+
+        let mut earliest: Option<f32> = None;
+        let mut consider = |t: Option<f32>| {
+            if let Some(t) = t
+                && t <= horizon
+                && earliest.is_none_or(|e| t < e)
+            {
+                earliest = Some(t);
+            }
+        };
+
+        for &(i, j) in &self.pairs {
+            let (a, b) = (&self.particles[i], &self.particles[j]);
+            consider(sphere_time_of_impact(
+                a.spatial_position() - b.spatial_position(),
+                a.three_velocity() - b.three_velocity(),
+                a.radius + b.radius,
+            ));
+        }
+
+        let faces = self.plane_faces();
+        for p in &self.particles {
+            for &(center, half, _) in &faces {
+                consider(face_time_of_impact(
+                    center,
+                    half,
+                    p.spatial_position(),
+                    p.three_velocity(),
+                    p.radius,
+                ));
+            }
+        }
+
+        earliest
+    }
+
+    /// Every face of every plane, with the plane's material.
+    fn plane_faces(&self) -> Vec<(Vector3<f32>, Vector3<f32>, MaterialId)> {
+        self.planes
+            .iter()
+            .flat_map(|plane| {
+                plane
+                    .faces()
+                    .into_iter()
+                    .map(|(center, half)| (center, half, plane.material))
+            })
+            .collect()
+    }
+
+    /// Fuses every pair of touching particles into one. Later particles move
+    /// down to fill the gaps, so indices past a merged particle change.
+    fn merge_touching(&mut self) {
+        self.boxes.clear();
+        self.boxes.extend(
+            self.particles
+                .iter()
+                .map(|p| Aabb::around_sphere(p.spatial_position(), p.radius)),
+        );
+        self.broadphase.find_pairs(&self.boxes, &mut self.pairs);
+        unlink_pairs(&mut self.pairs, &self.constraints);
+        if self.pairs.is_empty() {
+            return;
+        }
+
+        // Where each particle went, itself while it is still around. A pair
+        // whose members already merged with others is checked against what
+        // they became.
+        let mut merged_into: Vec<usize> = (0..self.particles.len()).collect();
+        let find = |merged_into: &[usize], mut i: usize| {
+            while merged_into[i] != i {
+                i = merged_into[i];
+            }
+            i
+        };
+
+        for &(i, j) in &self.pairs {
+            let (i, j) = (find(&merged_into, i), find(&merged_into, j));
+            if i == j {
+                continue;
+            }
+
+            let (a, b) = (&self.particles[i], &self.particles[j]);
+            let radius_sum = a.radius + b.radius;
+            if (a.spatial_position() - b.spatial_position()).magnitude2() > radius_sum * radius_sum
+            {
+                continue;
+            }
+
+            let Some(merged) = merge_particles(a, b) else {
+                continue;
+            };
+            let (keep, gone) = (i.min(j), i.max(j));
+            self.particles[keep] = merged;
+            merged_into[gone] = keep;
+        }
+
+        let mut index = 0;
+        self.particles.retain(|_| {
+            let alive = merged_into[index] == index;
+            index += 1;
+            alive
+        });
+
+        // Constraints follow their particles, and go once both ends merged
+        let mut new_index = vec![0; merged_into.len()];
+        let mut alive = 0;
+        for i in 0..merged_into.len() {
+            if merged_into[i] == i {
+                new_index[i] = alive;
+                alive += 1;
+            }
+        }
+        self.constraints.retain_mut(|c| {
+            c.a = new_index[find(&merged_into, c.a)];
+            c.b = new_index[find(&merged_into, c.b)];
+            c.a != c.b
+        });
+        for surface in &mut self.surfaces {
+            for t in &mut surface.triangles {
+                *t = t.map(|i| new_index[find(&merged_into, i)]);
+            }
+            surface
+                .triangles
+                .retain(|&[a, b, c]| a != b && b != c && c != a);
+        }
+    }
+
+    /// Advances the world by `dt` without looking for impacts inside the step.
+    fn step(&mut self, dt: f32) {
+        // Phase 1: Advance positions and velocities with the world's integrator.
+        // Accelerations are rebuilt from the forces whenever it asks for them.
+        let n_body = self.n_body;
+        let octree = &mut self.octree;
+        let em_field = &self.em_field;
+        let coulomb = self.coulomb;
+        let gravity = self.gravity;
+        let force_fields = &self.force_fields;
+        let pair_potentials = &mut self.pair_potentials;
+        let constraints = &self.constraints;
+        let sph = &mut self.sph;
+        self.integrator
+            .step(&mut self.particles, self.t, dt, &mut |particles, t| {
+                for p in particles.iter_mut() {
+                    (p.e_field, p.b_field) = match em_field {
+                        Some(field) => field.at(p.spatial_position(), t),
+                        None => (Vector3::zero(), Vector3::zero()),
+                    };
+                }
+                // Before the accelerations are cleared, as the radiation
+                // field of each charge depends on its last one
+                if let Some(coulomb) = &coulomb {
+                    coulomb.add_fields(particles);
+                }
+
+                for p in particles.iter_mut() {
+                    p.acceleration = p.base_acceleration + gravity;
+                    p.acceleration.x = 0.0;
+                    for field in force_fields {
+                        let a = field.acceleration(p, t);
+                        p.acceleration += Vector4::new(0.0, a.x, a.y, a.z);
+                    }
+                    if p.charge != 0.0 {
+                        p.acceleration += lorentz_force(p, p.e_field, p.b_field) / p.gamma();
+                    }
+                }
+                if let Some(n_body) = &n_body {
+                    n_body.accelerate_with(particles, octree);
+                }
+                if let Some(potentials) = pair_potentials {
+                    potentials.accelerate(particles);
+                }
+                constraint::accelerate(constraints, particles);
+                if let Some(sph) = sph {
+                    sph.accelerate(particles);
+                }
+            });
+
+        constraint::solve(
+            &self.constraints,
+            &mut self.particles,
+            dt,
+            self.constraint_iterations,
+        );
+
+        if let Some(sph) = &self.sph {
+            sph.paint(&mut self.particles);
+        }
+
+        if self.collision_mode == CollisionMode::Merge {
+            self.merge_touching();
         }
 
         // Phase 2: Handle inter-particle collisions.
@@ -273,96 +800,200 @@ impl PhysicsWorld {
             .iter()
             .map(|p| Vector3::new(p.v[1], p.v[2], p.v[3]))
             .collect();
+        let mut new_omegas: Vec<Vector3<f32>> = self.particles.iter().map(|p| p.omega).collect();
+        let mut new_masses: Vec<f32> = self.particles.iter().map(|p| p.mass).collect();
+        let materials = &self.materials;
+
+        // Position changes that separate overlapping particles. They are only
+        // applied once all contacts are seen, and never touch velocities, so
+        // they cannot add kinetic energy.
+        let mut corrections = vec![Vector3::zero(); self.particles.len()];
+        let PositionCorrection { slop, baumgarte } = self.correction;
+
+        // Only pairs whose bounding boxes overlap reach the exact sphere test.
+        self.boxes.clear();
+        self.boxes.extend(
+            self.particles
+                .iter()
+                .map(|p| Aabb::around_sphere(p.spatial_position(), p.radius)),
+        );
+        self.broadphase.find_pairs(&self.boxes, &mut self.pairs);
+        unlink_pairs(&mut self.pairs, &self.constraints);
+
+        // Merged particles have already fused with everything they touched,
+        // and fluid particles are kept apart by pressure
+        if self.collision_mode == CollisionMode::Merge || self.sph.is_some() {
+            self.pairs.clear();
+        }
+
+        for &(i, j) in &self.pairs {
+            // Extract 3D spatial position from 4D position Vector4
+            let p1_pos_spatial = Vector3::new(
+                self.particles[i].position[1],
+                self.particles[i].position[2],
+                self.particles[i].position[3],
+            );
+            let p2_pos_spatial = Vector3::new(
+                self.particles[j].position[1],
+                self.particles[j].position[2],
+                self.particles[j].position[3],
+            );
+
+            // Use velocities from the `new_vs` buffer as they might have been
+            // adjusted by previous collisions in this same timestep.
+            let p1_v = new_vs[i];
+            let p2_v = new_vs[j];
+
+            let p1_mass = new_masses[i];
+            let p2_mass = new_masses[j];
+            let p1_radius = self.particles[i].radius;
+            let p2_radius = self.particles[j].radius;
+
+            // Relative position vector (from p2 to p1)
+            let relative_pos = p1_pos_spatial - p2_pos_spatial;
+            let dist_sq = relative_pos.magnitude2(); // Squared distance
+            let radius_sum = p1_radius + p2_radius;
 
-        for i in 0..num_particles {
-            for j in (i + 1)..num_particles {
-                // Extract 3D spatial position from 4D position Vector4
-                let p1_pos_spatial = Vector3::new(
-                    self.particles[i].position[1],
-                    self.particles[i].position[2],
-                    self.particles[i].position[3],
-                );
-                let p2_pos_spatial = Vector3::new(
-                    self.particles[j].position[1],
-                    self.particles[j].position[2],
-                    self.particles[j].position[3],
-                );
-
-                // Use velocities from the `new_vs` buffer as they might have been
-                // adjusted by previous collisions in this same timestep.
-                let p1_v = new_vs[i];
-                let p2_v = new_vs[j];
-
-                let p1_mass = self.particles[i].mass;
-                let p2_mass = self.particles[j].mass;
-                let p1_radius = self.particles[i].radius;
-                let p2_radius = self.particles[j].radius;
-
-                // Relative position vector (from p2 to p1)
-                let relative_pos = p1_pos_spatial - p2_pos_spatial;
-                let dist_sq = relative_pos.magnitude2(); // Squared distance
-                let radius_sum = p1_radius + p2_radius;
-
-                // Check for collision: if distance <= sum of radii
-                // Also ensure distance is not near zero to prevent division by zero for normal vector.
-                if dist_sq <= radius_sum * radius_sum && dist_sq > 1e-6 {
-                    // Use small epsilon to avoid exactly zero distance
-                    let dist = dist_sq.sqrt();
-
-                    // Overlap correction: Slightly separate particles if they are too close/overlapping.
-                    let overlap = radius_sum - dist;
-                    if overlap > 0.0 {}
-
-                    // Collision normal vector (points from p2 to p1)
-                    let normal = relative_pos.normalize(); // Use cgmath's normalize method
-
-                    // Relative velocity (p1_v - p2_v)
-                    let relative_velocity = p1_v - p2_v;
-
-                    // Relative velocity along the collision normal
-                    let vel_along_normal = relative_velocity.dot(normal); // Use cgmath's dot method
-
-                    // Only resolve if particles are moving towards each other (closing in)
-                    if vel_along_normal < 0.0 {
-                        // Coefficient of restitution (e = 1.0 for perfectly elastic collision)
-                        let e = 1.0;
-
-                        // Calculate impulse magnitude (j)
-                        // j = -(1 + e) * vel_along_normal / (1/m1 + 1/m2)
-                        let impulse_magnitude =
-                            -(1.0 + e) * vel_along_normal / (1.0 / p1_mass + 1.0 / p2_mass);
-
-                        // Apply impulse to update velocities
-                        // Delta_v = impulse_magnitude * normal / mass
-                        new_vs[i] += normal * (impulse_magnitude / p1_mass);
-                        new_vs[j] -= normal * (impulse_magnitude / p2_mass); // p2 gets impulse in opposite direction
+            // Check for collision: if distance <= sum of radii
+            // Also ensure distance is not near zero to prevent division by zero for normal vector.
+            if dist_sq <= radius_sum * radius_sum && dist_sq > 1e-6 {
+                let dist = dist_sq.sqrt();
+
+                // Collision normal vector (points from p2 to p1)
+                let normal = relative_pos / dist;
+
+                // Overlap correction: move both apart along the normal, the
+                // lighter one further, removing part of the overlap each step.
+                let overlap = radius_sum - dist - slop * p1_radius.min(p2_radius);
+                if overlap > 0.0 {
+                    let (w1, w2) = (1.0 / p1_mass, 1.0 / p2_mass);
+                    let push = normal * (baumgarte * overlap / (w1 + w2));
+                    corrections[i] += push * w1;
+                    corrections[j] -= push * w2;
+                }
+
+                // Relative velocity (p1_v - p2_v)
+                let relative_velocity = p1_v - p2_v;
+
+                // Relative velocity along the collision normal
+                let vel_along_normal = relative_velocity.dot(normal); // Use cgmath's dot method
+
+                // Only resolve if particles are moving towards each other (closing in)
+                if vel_along_normal < 0.0 {
+                    let contact = lookup_material(materials, self.particles[i].material)
+                        .combine(lookup_material(materials, self.particles[j].material));
+
+                    // Resolve in the centre-of-momentum frame so 4-momentum is
+                    // conserved at any speed, not just in the Newtonian limit.
+                    if let Some(((m1, v1), (m2, v2))) = resolve_collision_cm(
+                        p1_mass,
+                        p1_v,
+                        p2_mass,
+                        p2_v,
+                        normal,
+                        contact.restitution,
+                        self.collision_mode == CollisionMode::Inelastic,
+                    ) {
+                        let (mut u1, mut u2) = (proper_from_three(v1), proper_from_three(v2));
+                        let normal_impulse =
+                            (u1 * m1 - proper_from_three(p1_v) * p1_mass).dot(normal);
+
+                        // Friction acts at the contact point, taken halfway through
+                        // the overlap so both lever arms end at the same place.
+                        let half_overlap = 0.5 * (radius_sum - dist);
+                        let r1 = -normal * (p1_radius - half_overlap);
+                        let r2 = normal * (p2_radius - half_overlap);
+                        let slip = (v1 + new_omegas[i].cross(r1)) - (v2 + new_omegas[j].cross(r2));
+                        let inv_mass = SPHERE_TANGENT_INV_MASS * (1.0 / m1 + 1.0 / m2);
+
+                        if let Some(impulse) =
+                            friction_impulse(slip, normal, normal_impulse, inv_mass, &contact)
+                        {
+                            u1 += impulse / m1;
+                            u2 -= impulse / m2;
+                            new_omegas[i] +=
+                                r1.cross(impulse) / self.particles[i].moment_of_inertia();
+                            new_omegas[j] -=
+                                r2.cross(impulse) / self.particles[j].moment_of_inertia();
+                        }
+
+                        new_vs[i] = three_from_proper(u1);
+                        new_vs[j] = three_from_proper(u2);
+                        new_masses[i] = m1;
+                        new_masses[j] = m2;
                     }
                 }
             }
         }
 
-        // Apply the updated 3-velocities back to the particles
-        for i in 0..num_particles {
-            self.particles[i].v = Vector4::new(C, new_vs[i][0], new_vs[i][1], new_vs[i][2]);
+        // Particle-plane contacts. Planes are immovable, so the normal component
+        // of the velocity is reflected and scaled by the combined restitution.
+        let faces = self.plane_faces();
+
+        let contacts = self
+            .particles
+            .iter()
+            .zip(&mut new_vs)
+            .zip(&mut new_omegas)
+            .zip(&mut corrections);
+        for (((p, v), omega), correction) in contacts {
+            for &(center, half, material) in &faces {
+                let Some((normal, depth)) =
+                    face_contact(center, half, p.spatial_position(), p.radius)
+                else {
+                    continue;
+                };
+
+                let overlap = depth - slop * p.radius;
+                if overlap > 0.0 {
+                    *correction += normal * (baumgarte * overlap);
+                }
+
+                let vel_along_normal = v.dot(normal);
+                if vel_along_normal < 0.0 {
+                    let contact = lookup_material(materials, p.material)
+                        .combine(lookup_material(materials, material));
+
+                    let bounced = *v - normal * ((1.0 + contact.restitution) * vel_along_normal);
+                    let mut u = proper_from_three(bounced);
+                    let normal_impulse = p.mass * (u - proper_from_three(*v)).dot(normal);
+
+                    let r = -normal * p.radius;
+                    let slip = bounced + omega.cross(r);
+                    let inv_mass = SPHERE_TANGENT_INV_MASS / p.mass;
+
+                    if let Some(impulse) =
+                        friction_impulse(slip, normal, normal_impulse, inv_mass, &contact)
+                    {
+                        u += impulse / p.mass;
+                        *omega += r.cross(impulse) / p.moment_of_inertia();
+                    }
+
+                    *v = three_from_proper(u);
+                }
+            }
         }
 
-        // Phase 3: Update 4-velocity, position, and proper time for all particles.
-        // This uses the final 3-velocities after collision resolution.
-        for p in self.particles.iter_mut() {
-            // Recompute the 4-velocity based on the updated 3-velocity (`p.v`)
-            p.velocity = normalize_4v(p.v);
+        // Apply the updated 3-velocities back to the particles,
+        // recomputing the 4-velocity from each.
+        let updates = new_vs
+            .into_iter()
+            .zip(new_omegas)
+            .zip(new_masses)
+            .zip(corrections);
+        for (p, (((v, omega), mass), dx)) in self.particles.iter_mut().zip(updates) {
+            p.set_three_velocity(v);
+            p.omega = omega;
+            p.mass = mass;
+            p.set_spatial_position(p.spatial_position() + dx);
+        }
 
+        // Phase 3: Update proper time and the time coordinate for all particles.
+        for p in self.particles.iter_mut() {
             // Update proper time (`tau`) using the new gamma from `p.velocity`
-            // Assuming p.velocity[0] holds gamma * C.
-            let gamma = p.velocity[0] / C; // Using p.velocity[0] to access the first component (ct)
-            let dtau = dt / gamma;
+            let dtau = dt / p.gamma();
             p.tau += dtau;
 
-            // Update spatial position components using the 4-velocity
-            p.position[1] += p.velocity[1] * dt; // x component
-            p.position[2] += p.velocity[2] * dt; // y component
-            p.position[3] += p.velocity[3] * dt; // z component
-
             // Update the time component of the 4-position.
             // This assumes a global time coordinate `t` for the simulation.
             p.position[0] = (self.t + dt) * C;
@@ -373,9 +1004,715 @@ impl PhysicsWorld {
     }
 }
 
+/// `1 / m + r^2 / I` for a push along the surface of a uniform solid sphere,
+/// in units of `1 / m`.
+const SPHERE_TANGENT_INV_MASS: f32 = 3.5;
+
+/// Coulomb friction impulse on the first body at a contact whose surfaces
+/// slide past each other with velocity `slip`. Sticks when stopping the slide
+/// needs less than the static limit, otherwise slides with dynamic friction.
+/// `inv_mass` is the pair's inverse mass along the surface.
+fn friction_impulse(
+    slip: Vector3<f32>,
+    normal: Vector3<f32>,
+    normal_impulse: f32,
+    inv_mass: f32,
+    contact: &ContactMaterial,
+) -> Option<Vector3<f32>> {
+    let tangential = slip - normal * slip.dot(normal);
+    let speed = tangential.magnitude();
+    if speed < 1e-9 || normal_impulse <= 0.0 {
+        return None;
+    }
+
+    let stop = speed / inv_mass;
+    let magnitude = if stop <= contact.static_friction * normal_impulse {
+        stop
+    } else {
+        stop.min(contact.dynamic_friction * normal_impulse)
+    };
+
+    Some(tangential * (-magnitude / speed))
+}
+
+/// Most sub-steps one update is split into for continuous collision detection.
+/// Past this the rest of the step is taken in one go, so dense crowds can't
+/// stall the simulation.
+const MAX_CCD_SUBSTEPS: usize = 64;
+
+/// Fraction of the contact distance that impacts are aimed inside of, so the
+/// pair is reliably touching once the sub-step ends.
+const CCD_SKIN: f32 = 1e-3;
+
+/// Time until two spheres with relative position `d`, relative velocity `w`
+/// and radii summing to `radius_sum` touch, if they are apart and closing.
+fn sphere_time_of_impact(d: Vector3<f32>, w: Vector3<f32>, radius_sum: f32) -> Option<f32> {
+    let b = d.dot(w);
+    if d.magnitude2() <= radius_sum * radius_sum || b >= 0.0 {
+        return None;
+    }
+
+    // |d + w t| = target, the earlier root
+    let target = radius_sum * (1.0 - CCD_SKIN);
+    let a = w.magnitude2();
+    let c = d.magnitude2() - target * target;
+    let disc = b * b - a * c;
+    if disc < 0.0 {
+        return None;
+    }
+
+    Some((-b - disc.sqrt()) / a)
+}
+
+/// Time until a sphere at `x` moving with `v` reaches a two-sided finite face,
+/// if it is apart from the face's plane and closing on it within the face.
+fn face_time_of_impact(
+    center: Vector3<f32>,
+    half: Vector3<f32>,
+    x: Vector3<f32>,
+    v: Vector3<f32>,
+    radius: f32,
+) -> Option<f32> {
+    let axis = face_axis(half);
+    let d = x - center;
+
+    let dist = d[axis].abs();
+    let closing = -v[axis] * d[axis].signum();
+    if dist <= radius || closing <= 0.0 {
+        return None;
+    }
+
+    let t = (dist - radius * (1.0 - CCD_SKIN)) / closing;
+    let at = d + v * t;
+    let within = (0..3).all(|a| a == axis || at[a].abs() <= half[a]);
+
+    within.then_some(t)
+}
+
+fn spatial(v: Vector4<f32>) -> Vector3<f32> {
+    Vector3::new(v[1], v[2], v[3])
+}
+
+/// Proper velocity `gamma * v` of the 3-velocity `v`.
+fn proper_from_three(v: Vector3<f32>) -> Vector3<f32> {
+    v / (1.0 - v.magnitude2() / (C * C)).sqrt()
+}
+
+fn three_from_proper(u: Vector3<f32>) -> Vector3<f32> {
+    u / gamma_from_proper(u)
+}
+
+/// The material `id` refers to, or the world's default one when there is no
+/// such material, or `Material::DEFAULT` when the world has none at all.
+fn lookup_material(materials: &[Material], id: MaterialId) -> &Material {
+    materials
+        .get(id)
+        .or(materials.first())
+        .unwrap_or(&Material::DEFAULT)
+}
+
+/// Lorentz factor for a proper velocity `u = gamma * v`.
+fn gamma_from_proper(u: Vector3<f32>) -> f32 {
+    (1.0 + u.magnitude2() / (C * C)).sqrt()
+}
+
+/// dv/dt of the coordinate velocity `v` under a force per rest mass `a`.
+fn coordinate_acceleration(u: Vector3<f32>, a: Vector3<f32>) -> Vector3<f32> {
+    let gamma = gamma_from_proper(u);
+    let v = u / gamma;
+
+    (a - v * (v.dot(a) / (C * C))) / gamma
+}
+
 fn normalize_4v(v: Vector4<f32>) -> Vector4<f32> {
     let v_sq = v[1].powi(2) + v[2].powi(2) + v[3].powi(2);
     let gamma = 1.0 / (1.0 - (v_sq / (C.powi(2)))).sqrt();
 
     v * gamma
 }
+
+/// 4-momentum `m * U` (as `[E/c, px, py, pz]`) of a particle of rest mass `m`
+/// moving with 3-velocity `v`.
+fn four_momentum(m: f64, v: Vector3<f64>) -> Vector4<f64> {
+    let c = C as f64;
+    let gamma = 1.0 / (1.0 - v.magnitude2() / (c * c)).sqrt();
+
+    Vector4::new(
+        gamma * m * c,
+        gamma * m * v.x,
+        gamma * m * v.y,
+        gamma * m * v.z,
+    )
+}
+
+/// Lorentz boost of the 4-vector `p` into a frame moving with velocity `beta * C`.
+fn boost(p: Vector4<f64>, beta: Vector3<f64>) -> Vector4<f64> {
+    let b_sq = beta.magnitude2();
+    if b_sq < 1e-30 {
+        return p;
+    }
+
+    let gamma = 1.0 / (1.0 - b_sq).sqrt();
+    let spatial = Vector3::new(p.y, p.z, p.w);
+    let b_dot_p = beta.dot(spatial);
+
+    let p0 = gamma * (p.x - b_dot_p);
+    let spatial = spatial + beta * ((gamma - 1.0) * b_dot_p / b_sq - gamma * p.x);
+
+    Vector4::new(p0, spatial.x, spatial.y, spatial.z)
+}
+
+/// Rest mass and 3-velocity of a particle leaving a collision.
+type Outgoing = (f32, Vector3<f32>);
+
+/// Resolves a contact between two spheres along `normal` (pointing from the
+/// second particle to the first).
+///
+/// Both 4-momenta are boosted into the centre-of-momentum frame, where the
+/// spatial momenta are equal and opposite, the normal components are reflected
+/// (scaled by `e`), each energy is put back on its mass shell and the result is
+/// boosted back to the lab frame. Returns the new rest masses and 3-velocities,
+/// or `None` if the particles are already separating in the CM frame.
+///
+/// With `heat` the energy taken out by `e < 1` is shared between the two in
+/// proportion to their masses and becomes rest mass, so the CM energy and the
+/// full 4-momentum are conserved. Otherwise the masses are unchanged and the
+/// energy is lost.
+///
+/// The maths is done in f64: at everyday speeds `gamma - 1` is far below f32
+/// resolution and the boost would otherwise lose the whole collision.
+fn resolve_collision_cm(
+    m1: f32,
+    v1: Vector3<f32>,
+    m2: f32,
+    v2: Vector3<f32>,
+    normal: Vector3<f32>,
+    e: f32,
+    heat: bool,
+) -> Option<(Outgoing, Outgoing)> {
+    let c = C as f64;
+    let (m1, m2, e) = (m1 as f64, m2 as f64, e as f64);
+    let n: Vector3<f64> = normal.cast()?;
+
+    let p1 = four_momentum(m1, v1.cast()?);
+    let p2 = four_momentum(m2, v2.cast()?);
+
+    // Velocity of the centre-of-momentum frame in units of C
+    let total = p1 + p2;
+    let beta = Vector3::new(total.y, total.z, total.w) / total.x;
+
+    let p1_cm = boost(p1, beta);
+    let p2_cm = boost(p2, beta);
+    let k1 = Vector3::new(p1_cm.y, p1_cm.z, p1_cm.w);
+    let k2 = Vector3::new(p2_cm.y, p2_cm.z, p2_cm.w);
+
+    // Relative velocity along the normal, measured in the CM frame
+    if (k1 / p1_cm.x - k2 / p2_cm.x).dot(n) >= 0.0 {
+        return None;
+    }
+
+    let k1 = k1 - n * ((1.0 + e) * k1.dot(n));
+    let k2 = k2 - n * ((1.0 + e) * k2.dot(n));
+
+    let on_shell = |m: f64, k: Vector3<f64>| {
+        Vector4::new(((m * c).powi(2) + k.magnitude2()).sqrt(), k.x, k.y, k.z)
+    };
+
+    let mut p1 = on_shell(m1, k1);
+    let mut p2 = on_shell(m2, k2);
+    let (mut m1, mut m2) = (m1, m2);
+
+    if heat {
+        let lost = (p1_cm.x + p2_cm.x) - (p1.x + p2.x);
+        p1.x += lost * m1 / (m1 + m2);
+        p2.x += lost * m2 / (m1 + m2);
+
+        // m c = sqrt((E / c)^2 - k^2)
+        m1 = (p1.x * p1.x - k1.magnitude2()).sqrt() / c;
+        m2 = (p2.x * p2.x - k2.magnitude2()).sqrt() / c;
+    }
+
+    let p1 = boost(p1, -beta);
+    let p2 = boost(p2, -beta);
+
+    let v1 = Vector3::new(p1.y, p1.z, p1.w) * (c / p1.x);
+    let v2 = Vector3::new(p2.y, p2.z, p2.w) * (c / p2.x);
+
+    Some(((m1 as f32, v1.cast()?), (m2 as f32, v2.cast()?)))
+}
+
+/// Drops pairs joined by a constraint, so they never collide with each other.
+fn unlink_pairs(pairs: &mut Vec<(usize, usize)>, constraints: &[Constraint]) {
+    if constraints.is_empty() {
+        return;
+    }
+    let linked: HashSet<(usize, usize)> = constraints
+        .iter()
+        .map(|c| (c.a.min(c.b), c.a.max(c.b)))
+        .collect();
+    pairs.retain(|&(i, j)| !linked.contains(&(i.min(j), i.max(j))));
+}
+
+/// One particle carrying the total 4-momentum of `a` and `b`, at their centre
+/// of energy. Its rest mass is the pair's invariant mass, so the kinetic
+/// energy of their relative motion ends up as rest mass.
+fn merge_particles(a: &Particle, b: &Particle) -> Option<Particle> {
+    let c = C as f64;
+    let pa = four_momentum(a.mass as f64, a.three_velocity().cast()?);
+    let pb = four_momentum(b.mass as f64, b.three_velocity().cast()?);
+
+    let p = pa + pb;
+    let k = Vector3::new(p.y, p.z, p.w);
+    let mass = (p.x * p.x - k.magnitude2()).sqrt() / c;
+    let v: Vector3<f32> = (k * (c / p.x)).cast()?;
+
+    let (wa, wb) = ((pa.x / p.x) as f32, (pb.x / p.x) as f32);
+    let x = a.spatial_position() * wa + b.spatial_position() * wb;
+
+    // Same total volume, colours blended by rest mass
+    let radius = (a.radius.powi(3) + b.radius.powi(3)).cbrt();
+    let share = a.mass / (a.mass + b.mass);
+    let color: [f32; 3] = std::array::from_fn(|k| a.color[k] * share + b.color[k] * (1.0 - share));
+
+    let heavier = if a.mass >= b.mass { a } else { b };
+    let mut merged = Particle::new(
+        Vector4::new(a.position[0], x.x, x.y, x.z),
+        Vector4::new(C, v.x, v.y, v.z),
+        mass as f32,
+        radius,
+        color,
+        heavier.tau,
+    );
+    merged.material = heavier.material;
+    merged.species = heavier.species;
+    merged.charge = a.charge + b.charge;
+
+    // Spin from the pair's angular momentum about the new centre
+    let angular_momentum = |p: &Particle| {
+        (p.spatial_position() - x).cross(p.proper_velocity() * p.mass)
+            + p.omega * p.moment_of_inertia()
+    };
+    merged.omega = (angular_momentum(a) + angular_momentum(b)) / merged.moment_of_inertia();
+
+    Some(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phys::material::Combine;
+
+    fn total_four_momentum(world: &PhysicsWorld) -> Vector4<f64> {
+        world
+            .particles
+            .iter()
+            .map(|p| p.velocity.cast::<f64>().unwrap() * p.mass as f64)
+            .fold(Vector4::zero(), |acc, p| acc + p)
+    }
+
+    fn invariant_mass(p: Vector4<f64>) -> f64 {
+        let c = C as f64;
+        (p.x * p.x - p.y * p.y - p.z * p.z - p.w * p.w).sqrt() / c
+    }
+
+    fn check_collision_at_gamma(gamma: f32) {
+        let speed = C * (1.0 - 1.0 / (gamma * gamma)).sqrt();
+
+        let mut world = PhysicsWorld::new();
+        // Off-centre contact with unequal masses so the CM frame is moving
+        // and the normal is not aligned with the motion.
+        world.add_particle(part![
+            0.0, 0.5, 0.0, -0.8;
+            0.0, 0.0, speed;
+            1.0; 0.5;
+            1.0, 1.0, 1.0
+        ]);
+        world.add_particle(part![
+            0.0, 0.0, 0.0, 0.0;
+            0.0, 0.0, -speed;
+            3.0; 0.5;
+            1.0, 1.0, 1.0
+        ]);
+
+        let before = total_four_momentum(&world);
+        let v_before = world.particles[0].v;
+
+        world.update(1e-12);
+
+        let after = total_four_momentum(&world);
+        assert_ne!(world.particles[0].v, v_before, "particles did not collide");
+
+        let (m_before, m_after) = (invariant_mass(before), invariant_mass(after));
+        assert!(
+            ((m_after - m_before) / m_before).abs() < 1e-4,
+            "invariant mass changed at gamma {}: {} -> {}",
+            gamma,
+            m_before,
+            m_after
+        );
+
+        for k in 0..4 {
+            assert!(
+                (after[k] - before[k]).abs() < 1e-4 * before.x,
+                "4-momentum component {} changed at gamma {}: {} -> {}",
+                k,
+                gamma,
+                before[k],
+                after[k]
+            );
+        }
+    }
+
+    #[test]
+    fn collision_conserves_invariant_mass_at_gamma_1_01() {
+        check_collision_at_gamma(1.01);
+    }
+
+    #[test]
+    fn collision_conserves_invariant_mass_at_gamma_2() {
+        check_collision_at_gamma(2.0);
+    }
+
+    #[test]
+    fn collision_conserves_invariant_mass_at_gamma_10() {
+        check_collision_at_gamma(10.0);
+    }
+
+    // Equal masses meeting head-on at gamma 2, so the CM frame is the lab frame
+    fn sticky_head_on_pair(mode: CollisionMode) -> PhysicsWorld {
+        let speed = C * 0.75f32.sqrt();
+        let mut world = PhysicsWorld::new();
+        world.collision_mode = mode;
+        world.materials[0].restitution = 0.0;
+        world.add_particle(part![0.0, -0.45, 0.0, 0.0; speed, 0.0, 0.0; 1.0; 0.5; 1.0, 0.0, 0.0]);
+        world.add_particle(part![0.0, 0.45, 0.0, 0.0; -speed, 0.0, 0.0; 1.0; 0.5; 0.0, 0.0, 1.0]);
+        world
+    }
+
+    #[test]
+    fn inelastic_collision_turns_lost_energy_into_rest_mass() {
+        let mut world = sticky_head_on_pair(CollisionMode::Inelastic);
+        let before = total_four_momentum(&world);
+
+        world.update(1e-12);
+
+        let after = total_four_momentum(&world);
+        for k in 0..4 {
+            assert!(
+                (after[k] - before[k]).abs() < 1e-4 * before.x,
+                "4-momentum component {} changed: {} -> {}",
+                k,
+                before[k],
+                after[k]
+            );
+        }
+
+        // Both stop dead and carry all of the energy as rest mass
+        for p in &world.particles {
+            assert!(p.three_velocity().magnitude() < 1e-3 * C);
+            assert!((p.mass - 2.0).abs() < 1e-3, "mass = {}", p.mass);
+        }
+    }
+
+    #[test]
+    fn bounce_mode_keeps_rest_masses() {
+        let mut world = sticky_head_on_pair(CollisionMode::Bounce);
+        world.update(1e-12);
+
+        for p in &world.particles {
+            assert!(p.three_velocity().magnitude() < 1e-3 * C);
+            assert_eq!(p.mass, 1.0);
+        }
+    }
+
+    #[test]
+    fn merged_particle_carries_total_four_momentum() {
+        let mut world = PhysicsWorld::new();
+        world.collision_mode = CollisionMode::Merge;
+        world.add_particle(part![0.0, -0.45, 0.0, 0.0; 0.6 * C, 0.0, 0.0; 3.0; 0.5; 1.0, 0.0, 0.0]);
+        world.add_particle(part![0.0, 0.45, 0.0, 0.0; 0.0, 0.0, 0.0; 1.0; 0.5; 0.0, 0.0, 1.0]);
+        world.add_particle(part![0.0, 0.0, 10.0, 0.0; 1.0; 1.0]);
+
+        let p = total_four_momentum(&world);
+        world.update(1e-12);
+
+        assert_eq!(world.particles.len(), 2);
+        let merged = &world.particles[0];
+
+        // Rest mass is the invariant mass of the pair, more than the sum
+        let pair = p - Vector4::new(C as f64, 0.0, 0.0, 0.0);
+        let mass = invariant_mass(pair);
+        assert!(mass > 4.0);
+        assert!(
+            ((merged.mass as f64 - mass) / mass).abs() < 1e-5,
+            "{} vs {}",
+            merged.mass,
+            mass
+        );
+
+        let v = pair.y / pair.x * C as f64;
+        assert!(
+            ((merged.three_velocity().x as f64 - v) / v).abs() < 1e-5,
+            "{:?} vs {}",
+            merged.three_velocity(),
+            v
+        );
+        assert!((merged.radius - 0.25f32.cbrt()).abs() < 1e-6);
+
+        // The bystander is untouched
+        assert_eq!(world.particles[1].spatial_position().y, 10.0);
+    }
+
+    #[test]
+    fn overlapping_particles_separate_without_gaining_energy() {
+        let mut world = PhysicsWorld::new();
+        world.add_particle(part![0.0, -0.5, 0.0, 0.0; 1.0; 1.0]);
+        world.add_particle(part![0.0, 0.5, 0.0, 0.0; 3.0; 1.0]);
+
+        for _ in 0..200 {
+            world.update(0.01);
+        }
+
+        let (a, b) = (&world.particles[0], &world.particles[1]);
+        let dist = (a.spatial_position() - b.spatial_position()).magnitude();
+        assert!(
+            dist > 2.0 * (1.0 - world.correction.slop),
+            "dist = {}",
+            dist
+        );
+        assert_eq!(world.kinetic_energy(), 0.0);
+
+        // The lighter particle moves three times as far
+        let moved_a = -0.5 - a.spatial_position().x;
+        let moved_b = b.spatial_position().x - 0.5;
+        assert!(
+            (moved_a - 3.0 * moved_b).abs() < 1e-4,
+            "{} vs {}",
+            moved_a,
+            moved_b
+        );
+    }
+
+    #[test]
+    fn particle_sunk_into_floor_is_pushed_out() {
+        let mut world = PhysicsWorld::new();
+        world
+            .planes
+            .push(plane![0, 0, 0; 10, 0, 10; [1.0, 1.0, 1.0]]);
+        world.add_particle(part![0.0, 0.0, 0.5, 0.0; 1.0; 1.0]);
+
+        for _ in 0..200 {
+            world.update(0.01);
+        }
+
+        let y = world.particles[0].spatial_position().y;
+        assert!(
+            (y - (1.0 - world.correction.slop)).abs() < 1e-3,
+            "y = {}",
+            y
+        );
+        assert_eq!(world.particles[0].three_velocity(), Vector3::zero());
+    }
+
+    // Each moves 50 m per step, far more than the 2 m they are wide
+    fn fast_head_on_pair(ccd: bool) -> PhysicsWorld {
+        let mut world = PhysicsWorld::new();
+        world.ccd = ccd;
+        world.add_particle(part![0.0, -30.0, 0.0, 0.0; 5000.0, 0.0, 0.0; 1.0; 1.0; 1.0, 1.0, 1.0]);
+        world.add_particle(part![0.0, 30.0, 0.0, 0.0; -5000.0, 0.0, 0.0; 1.0; 1.0; 1.0, 1.0, 1.0]);
+        world.update(0.01);
+        world
+    }
+
+    #[test]
+    fn fast_particles_pass_through_each_other_without_ccd() {
+        let world = fast_head_on_pair(false);
+        assert!(world.particles[0].spatial_position().x > 0.0);
+        assert!(world.particles[0].three_velocity().x > 0.0);
+    }
+
+    #[test]
+    fn ccd_catches_fast_particle_impacts() {
+        let world = fast_head_on_pair(true);
+        let (a, b) = (&world.particles[0], &world.particles[1]);
+
+        assert!(a.spatial_position().x < b.spatial_position().x);
+        assert!(
+            (a.three_velocity().x + 5000.0).abs() < 1.0,
+            "{:?}",
+            a.three_velocity()
+        );
+        assert!(
+            (b.three_velocity().x - 5000.0).abs() < 1.0,
+            "{:?}",
+            b.three_velocity()
+        );
+        assert!((world.time() - 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ccd_stops_tunnelling_through_walls() {
+        for ccd in [false, true] {
+            let mut world = PhysicsWorld::new();
+            world.ccd = ccd;
+            world
+                .planes
+                .push(plane![0, 0, 0; 0, 10, 10; [1.0, 1.0, 1.0]]);
+            world.add_particle(
+                part![0.0, -20.0, 0.0, 0.0; 3000.0, 0.0, 0.0; 1.0; 0.5; 1.0, 1.0, 1.0],
+            );
+
+            world.update(0.01);
+
+            let x = world.particles[0].spatial_position().x;
+            assert_eq!(x < 0.0, ccd, "ccd {}: x = {}", ccd, x);
+        }
+    }
+
+    #[test]
+    fn particle_bounces_off_floor() {
+        let mut world = PhysicsWorld::new();
+        world.materials.push(Material {
+            restitution: 0.5,
+            restitution_combine: Combine::Min,
+            ..Default::default()
+        });
+        world
+            .planes
+            .push(plane![0, 0, 0; 10, 0, 10; [1.0, 1.0, 1.0]; 1]);
+        world.add_particle(part![0.0, 0.0, 0.9, 0.0; 0.0, -2.0, 0.0; 1.0; 1.0; 1.0, 1.0, 1.0]);
+
+        world.update(0.01);
+
+        let v = world.particles[0].three_velocity();
+        assert!((v.y - 1.0).abs() < 1e-4, "v = {:?}", v);
+    }
+
+    #[test]
+    fn base_acceleration_keeps_acting() {
+        let mut world = PhysicsWorld::new();
+        let mut p = part![0.0, 0.0, 0.0, 0.0; 1.0; 0.1];
+        p.base_acceleration = Vector4::new(0.0, 2.0, 0.0, 0.0);
+        world.add_particle(p);
+
+        for _ in 0..100 {
+            world.update(0.01);
+        }
+
+        let v = world.particles[0].three_velocity();
+        assert!((v.x - 2.0).abs() < 1e-3, "v = {:?}", v);
+    }
+
+    #[test]
+    fn unknown_material_falls_back_to_the_default() {
+        // Also with no materials at all
+        for keep_materials in [true, false] {
+            let mut world = PhysicsWorld::new();
+            if !keep_materials {
+                world.materials.clear();
+            }
+            world
+                .planes
+                .push(plane![0, 0, 0; 10, 0, 10; [1.0, 1.0, 1.0]; 7]);
+            let mut p = part![0.0, 0.0, 0.9, 0.0; 0.0, -2.0, 0.0; 1.0; 1.0; 1.0, 1.0, 1.0];
+            p.material = 3;
+            world.add_particle(p);
+
+            world.update(0.01);
+
+            let v = world.particles[0].three_velocity();
+            assert!((v.y - 2.0).abs() < 1e-4, "v = {:?}", v);
+        }
+    }
+
+    fn grippy() -> Material {
+        Material {
+            static_friction: 1.0,
+            dynamic_friction: 0.8,
+            friction_combine: Combine::Max,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn friction_makes_a_skidding_ball_roll() {
+        let mut world = PhysicsWorld::new();
+        world.materials.push(grippy());
+        world
+            .planes
+            .push(plane![0, 0, 0; 10, 0, 10; [1.0, 1.0, 1.0]; 1]);
+        world.add_particle(part![0.0, 0.0, 0.99, 0.0; 4.0, -2.0, 0.0; 1.0; 1.0; 1.0, 1.0, 1.0]);
+
+        world.update(0.001);
+
+        // A solid sphere that starts rolling keeps 5/7 of its sliding speed
+        let p = &world.particles[0];
+        let v = p.three_velocity();
+        assert!((v.x - 4.0 * 5.0 / 7.0).abs() < 1e-3, "v = {:?}", v);
+
+        let contact_velocity = v + p.omega.cross(Vector3::new(0.0, -p.radius, 0.0));
+        assert!(contact_velocity.x.abs() < 1e-3, "{:?}", contact_velocity);
+    }
+
+    #[test]
+    fn friction_conserves_momentum_and_angular_momentum() {
+        let mut world = PhysicsWorld::new();
+        world.materials.push(grippy());
+        world.add_particle(part![0.0, -1.0887, 0.5, 0.0; 3.0, 0.0, 1.0; 1.0; 0.6; 1.0, 1.0, 1.0]);
+        world.add_particle(part![0.0, 0.0, 0.0, 0.0; 0.0, 0.0, -1.0; 2.0; 0.6; 1.0, 1.0, 1.0]);
+        for p in world.particles.iter_mut() {
+            p.material = 1;
+        }
+
+        let momenta = |world: &PhysicsWorld| {
+            let mut linear = Vector3::zero();
+            let mut angular = Vector3::zero();
+            for p in &world.particles {
+                let momentum = p.proper_velocity() * p.mass;
+                linear += momentum;
+                angular += p.spatial_position().cross(momentum) + p.omega * p.moment_of_inertia();
+            }
+            (linear, angular)
+        };
+
+        let (linear, angular) = momenta(&world);
+        // Glancing contact, just inside the slop so no position correction
+        world.update(1e-6);
+        let (linear_after, angular_after) = momenta(&world);
+
+        assert!(world.particles[0].omega.magnitude() > 0.1);
+        assert!((linear_after - linear).magnitude() < 1e-5);
+        assert!(
+            (angular_after - angular).magnitude() < 1e-4,
+            "{:?} -> {:?}",
+            angular,
+            angular_after
+        );
+    }
+
+    #[test]
+    fn particle_misses_edge_of_finite_plane() {
+        let mut world = PhysicsWorld::new();
+        world
+            .planes
+            .push(plane![0, 0, 0; 10, 0, 10; [1.0, 1.0, 1.0]]);
+        world.add_particle(part![0.0, 7.0, 0.5, 0.0; 0.0, -2.0, 0.0; 1.0; 1.0; 1.0, 1.0, 1.0]);
+
+        world.update(0.01);
+
+        assert!(world.particles[0].three_velocity().y < 0.0);
+    }
+
+    #[test]
+    fn box_keeps_particle_inside() {
+        let mut world = PhysicsWorld::new();
+        world.planes.push(plane![0, 0, 0; 4, 4, 4; [1.0, 1.0, 1.0]]);
+        world.add_particle(part![0.0, 0.0, 0.0, 0.0; 3.0, 0.0, 1.0; 1.0; 0.5; 1.0, 1.0, 1.0]);
+
+        for _ in 0..1000 {
+            world.update(0.01);
+            let x = world.particles[0].spatial_position();
+            assert!(x.x.abs() < 2.0 && x.z.abs() < 2.0, "escaped to {:?}", x);
+        }
+    }
+}
@@ -0,0 +1,628 @@
+use cgmath::Vector3;
+
+/// Axis-aligned bounding box, used to find pairs that might be touching before
+/// the exact sphere test.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn around_sphere(center: Vector3<f32>, radius: f32) -> Self {
+        let r = Vector3::new(radius, radius, radius);
+        Aabb {
+            min: center - r,
+            max: center + r,
+        }
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+            && self.min.z <= other.max.z
+            && other.min.z <= self.max.z
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && self.min.z <= other.min.z
+            && other.max.x <= self.max.x
+            && other.max.y <= self.max.y
+            && other.max.z <= self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vector3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Vector3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    /// Grown by `margin` on every side.
+    pub fn inflated(&self, margin: f32) -> Aabb {
+        let m = Vector3::new(margin, margin, margin);
+        Aabb {
+            min: self.min - m,
+            max: self.max + m,
+        }
+    }
+
+    /// Surface area, the usual cost measure for building bounding volume trees.
+    pub fn area(&self) -> f32 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    fn largest_extent(&self) -> f32 {
+        let d = self.max - self.min;
+        d.x.max(d.y).max(d.z)
+    }
+}
+
+/// Finds the pairs of bounding boxes that overlap, so only those reach the
+/// exact contact test.
+///
+/// Implementations may keep state between calls to speed up the next one,
+/// but must not depend on it for correctness: every call sees a fresh slice
+/// of boxes, indexed like the world's particles.
+pub trait Broadphase: Send {
+    fn name(&self) -> &'static str;
+
+    /// Writes every overlapping pair `(i, j)` with `i < j` to `out`, sorted so
+    /// the narrowphase sees them in the same order as the naive double loop.
+    fn find_pairs(&mut self, boxes: &[Aabb], out: &mut Vec<(usize, usize)>);
+}
+
+/// Looks up a broadphase by the name used in scene files.
+pub fn by_name(name: &str) -> Option<Box<dyn Broadphase>> {
+    match name {
+        "brute" => Some(Box::new(BruteForce)),
+        "grid" => Some(Box::new(SpatialHash::new())),
+        "sweep-x" => Some(Box::new(SweepAndPrune::new(0))),
+        "sweep-y" => Some(Box::new(SweepAndPrune::new(1))),
+        "sweep-z" => Some(Box::new(SweepAndPrune::new(2))),
+        "tree" => Some(Box::new(AabbTree::new())),
+        _ => None,
+    }
+}
+
+/// Tests every pair of boxes. Reference for the faster broadphases.
+pub struct BruteForce;
+
+impl Broadphase for BruteForce {
+    fn name(&self) -> &'static str {
+        "brute"
+    }
+
+    fn find_pairs(&mut self, boxes: &[Aabb], out: &mut Vec<(usize, usize)>) {
+        out.clear();
+
+        for i in 0..boxes.len() {
+            for j in (i + 1)..boxes.len() {
+                if boxes[i].overlaps(&boxes[j]) {
+                    out.push((i, j));
+                }
+            }
+        }
+    }
+}
+
+/// Uniform grid with cells as wide as the largest box. Fastest when sizes are
+/// similar; a few large boxes make every cell crowded.
+///
+/// Each box is filed under the cell holding its low corner. Since no box is
+/// wider than a cell, two boxes can only overlap if those cells are equal or
+/// adjacent, so each cell is checked against itself and the half of its 26
+/// neighbours that come after it.
+///
+/// Occupied cells are kept as a sorted list of `(cell, box)` entries rather
+/// than a hash map, which needs no per-cell allocation and is reused between
+/// steps.
+#[derive(Default)]
+pub struct SpatialHash {
+    entries: Vec<(u64, u32)>,
+    // (cell, first entry, one past the last entry)
+    runs: Vec<(u64, usize, usize)>,
+}
+
+// Cell coordinates are packed into 21 bits each
+const CELL_BITS: u32 = 21;
+const CELL_OFFSET: i32 = 1 << (CELL_BITS - 1);
+
+/// `(dx, dy, lowest dz)` of the runs of neighbouring cells after a cell, each
+/// running up to `dz = 1`: the next cell along z, the column at `(x, y + 1)`
+/// and the three at `(x + 1, y + dy)`.
+const NEIGHBOUR_COLUMNS: [(i32, i32, i32); 5] =
+    [(0, 0, 1), (0, 1, -1), (1, -1, -1), (1, 0, -1), (1, 1, -1)];
+
+impl SpatialHash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Broadphase for SpatialHash {
+    fn name(&self) -> &'static str {
+        "grid"
+    }
+
+    fn find_pairs(&mut self, boxes: &[Aabb], out: &mut Vec<(usize, usize)>) {
+        out.clear();
+
+        let largest = boxes.iter().map(Aabb::largest_extent).fold(0.0, f32::max);
+        let cell = if largest.is_finite() && largest > 0.0 {
+            largest
+        } else {
+            1.0
+        };
+
+        self.entries.clear();
+        self.entries.extend(
+            boxes
+                .iter()
+                .enumerate()
+                .map(|(i, b)| (pack(cell_of(b.min, cell)), i as u32)),
+        );
+        self.entries.sort_unstable();
+
+        self.runs.clear();
+        let mut start = 0;
+        for run in self.entries.chunk_by(|a, b| a.0 == b.0) {
+            self.runs.push((run[0].0, start, start + run.len()));
+            start += run.len();
+        }
+
+        let entries = &self.entries;
+        let runs = &self.runs;
+        let mut test = |i: u32, j: u32| {
+            let (i, j) = (i as usize, j as usize);
+            if boxes[i].overlaps(&boxes[j]) {
+                out.push((i.min(j), i.max(j)));
+            }
+        };
+
+        let mut cursors = [0; NEIGHBOUR_COLUMNS.len()];
+        for &(key, start, end) in runs {
+            let run = &entries[start..end];
+
+            for (k, &(_, i)) in run.iter().enumerate() {
+                for &(_, j) in &run[k + 1..] {
+                    test(i, j);
+                }
+            }
+
+            // Neighbours later in key order come in five columns along z.
+            // Their keys only grow from one cell to the next, so each column keeps a cursor
+            // into `runs` instead of searching for it.
+            let [x, y, z] = unpack(key);
+
+            for ((dx, dy, z_lo), cursor) in NEIGHBOUR_COLUMNS.into_iter().zip(&mut cursors) {
+                let first = pack([x + dx, y + dy, z + z_lo]);
+                let last = pack([x + dx, y + dy, z + 1]);
+
+                while *cursor < runs.len() && runs[*cursor].0 < first {
+                    *cursor += 1;
+                }
+
+                for &(_, other_start, other_end) in
+                    runs[*cursor..].iter().take_while(|run| run.0 <= last)
+                {
+                    for &(_, i) in run {
+                        for &(_, j) in &entries[other_start..other_end] {
+                            test(i, j);
+                        }
+                    }
+                }
+            }
+        }
+
+        out.sort_unstable();
+    }
+}
+
+/// Sorts the boxes by their lower bound on one axis and sweeps along it, only
+/// testing boxes whose intervals on that axis overlap. Unaffected by mixed
+/// sizes, but works best along an axis the particles are spread out on.
+pub struct SweepAndPrune {
+    axis: usize,
+    // Kept between steps; nearly sorted already, which the sort exploits
+    order: Vec<usize>,
+}
+
+impl SweepAndPrune {
+    /// Sweeps along x, y or z for an `axis` of 0, 1 or 2.
+    pub fn new(axis: usize) -> Self {
+        assert!(axis < 3, "sweep axis must be 0, 1 or 2, got {}", axis);
+        SweepAndPrune {
+            axis,
+            order: Vec::new(),
+        }
+    }
+}
+
+impl Broadphase for SweepAndPrune {
+    fn name(&self) -> &'static str {
+        ["sweep-x", "sweep-y", "sweep-z"][self.axis]
+    }
+
+    fn find_pairs(&mut self, boxes: &[Aabb], out: &mut Vec<(usize, usize)>) {
+        out.clear();
+
+        let axis = self.axis;
+        if self.order.len() != boxes.len() {
+            self.order = (0..boxes.len()).collect();
+        }
+        self.order
+            .sort_by(|&a, &b| boxes[a].min[axis].total_cmp(&boxes[b].min[axis]));
+
+        for (k, &i) in self.order.iter().enumerate() {
+            let a = &boxes[i];
+
+            for &j in self.order[k + 1..]
+                .iter()
+                .take_while(|&&j| boxes[j].min[axis] <= a.max[axis])
+            {
+                if a.overlaps(&boxes[j]) {
+                    out.push((i.min(j), i.max(j)));
+                }
+            }
+        }
+
+        out.sort_unstable();
+    }
+}
+
+const NULL_NODE: usize = usize::MAX;
+
+#[derive(Clone, Debug)]
+struct TreeNode {
+    aabb: Aabb,
+    parent: usize,
+    children: [usize; 2],
+    // Box index for leaves, NULL_NODE for internal nodes
+    item: usize,
+}
+
+/// Dynamic bounding volume tree over slightly enlarged boxes.
+///
+/// Leaves persist between steps and are only reinserted once a box moves out
+/// of its enlarged one, so slow movers cost nothing to update. Insertion picks
+/// the sibling that grows the tree's surface area least, which keeps queries
+/// fast regardless of how mixed the box sizes are.
+#[derive(Default)]
+pub struct AabbTree {
+    nodes: Vec<TreeNode>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    // Leaf node of each box
+    leaves: Vec<usize>,
+    // Pairs of nodes still to visit while walking the tree against itself
+    stack: Vec<(usize, usize)>,
+}
+
+/// Fraction of a box's size added on every side of its leaf.
+const TREE_MARGIN: f32 = 0.1;
+
+impl AabbTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = None;
+        self.leaves.clear();
+    }
+
+    fn allocate(&mut self, node: TreeNode) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        let Some(root) = self.root else {
+            self.nodes[leaf].parent = NULL_NODE;
+            self.root = Some(leaf);
+            return;
+        };
+
+        // Walk down towards whichever child grows least by taking the leaf
+        let aabb = self.nodes[leaf].aabb;
+        let mut sibling = root;
+        while self.nodes[sibling].item == NULL_NODE {
+            let node = &self.nodes[sibling];
+            let combined = node.aabb.union(&aabb).area();
+
+            // Cost of making the leaf a sibling of this node right here
+            let cost_here = 2.0 * combined;
+            let inherited = 2.0 * (combined - node.aabb.area());
+
+            let descend_cost = |child: usize| {
+                let child = &self.nodes[child];
+                let grown = child.aabb.union(&aabb).area();
+                if child.item == NULL_NODE {
+                    grown - child.aabb.area() + inherited
+                } else {
+                    grown + inherited
+                }
+            };
+
+            let [left, right] = node.children;
+            let (cost_left, cost_right) = (descend_cost(left), descend_cost(right));
+            if cost_here < cost_left && cost_here < cost_right {
+                break;
+            }
+
+            sibling = if cost_left < cost_right { left } else { right };
+        }
+
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate(TreeNode {
+            aabb: self.nodes[sibling].aabb.union(&aabb),
+            parent: old_parent,
+            children: [sibling, leaf],
+            item: NULL_NODE,
+        });
+        self.nodes[sibling].parent = new_parent;
+        self.nodes[leaf].parent = new_parent;
+
+        if old_parent == NULL_NODE {
+            self.root = Some(new_parent);
+        } else {
+            let children = &mut self.nodes[old_parent].children;
+            let slot = if children[0] == sibling { 0 } else { 1 };
+            children[slot] = new_parent;
+        }
+
+        self.refit(old_parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        let parent = self.nodes[leaf].parent;
+        if parent == NULL_NODE {
+            self.root = None;
+            return;
+        }
+
+        let [left, right] = self.nodes[parent].children;
+        let sibling = if left == leaf { right } else { left };
+        let grandparent = self.nodes[parent].parent;
+
+        self.nodes[sibling].parent = grandparent;
+        self.free.push(parent);
+
+        if grandparent == NULL_NODE {
+            self.root = Some(sibling);
+        } else {
+            let children = &mut self.nodes[grandparent].children;
+            let slot = if children[0] == parent { 0 } else { 1 };
+            children[slot] = sibling;
+            self.refit(grandparent);
+        }
+    }
+
+    /// Recomputes the boxes of `node` and its ancestors from their children.
+    fn refit(&mut self, mut node: usize) {
+        while node != NULL_NODE {
+            let [left, right] = self.nodes[node].children;
+            self.nodes[node].aabb = self.nodes[left].aabb.union(&self.nodes[right].aabb);
+            node = self.nodes[node].parent;
+        }
+    }
+
+    fn enlarged(aabb: &Aabb) -> Aabb {
+        aabb.inflated(aabb.largest_extent() * TREE_MARGIN)
+    }
+}
+
+impl Broadphase for AabbTree {
+    fn name(&self) -> &'static str {
+        "tree"
+    }
+
+    fn find_pairs(&mut self, boxes: &[Aabb], out: &mut Vec<(usize, usize)>) {
+        out.clear();
+
+        if self.leaves.len() != boxes.len() {
+            self.clear();
+            for (i, b) in boxes.iter().enumerate() {
+                let leaf = self.allocate(TreeNode {
+                    aabb: Self::enlarged(b),
+                    parent: NULL_NODE,
+                    children: [NULL_NODE; 2],
+                    item: i,
+                });
+                self.leaves.push(leaf);
+                self.insert_leaf(leaf);
+            }
+        } else {
+            for (i, b) in boxes.iter().enumerate() {
+                let leaf = self.leaves[i];
+                if !self.nodes[leaf].aabb.contains(b) {
+                    self.remove_leaf(leaf);
+                    self.nodes[leaf].aabb = Self::enlarged(b);
+                    self.insert_leaf(leaf);
+                }
+            }
+        }
+
+        let Some(root) = self.root else {
+            return;
+        };
+
+        // Walk the tree against itself: a node paired with itself splits into
+        // its children's pairs, and two different nodes are only opened up,
+        // larger one first, while their boxes overlap.
+        self.stack.clear();
+        self.stack.push((root, root));
+
+        while let Some((a, b)) = self.stack.pop() {
+            let (node_a, node_b) = (&self.nodes[a], &self.nodes[b]);
+
+            if a == b {
+                if node_a.item == NULL_NODE {
+                    let [left, right] = node_a.children;
+                    self.stack
+                        .extend([(left, left), (right, right), (left, right)]);
+                }
+                continue;
+            }
+
+            if !node_a.aabb.overlaps(&node_b.aabb) {
+                continue;
+            }
+
+            let (a_leaf, b_leaf) = (node_a.item != NULL_NODE, node_b.item != NULL_NODE);
+
+            if a_leaf && b_leaf {
+                let (i, j) = (node_a.item, node_b.item);
+                if boxes[i].overlaps(&boxes[j]) {
+                    out.push((i.min(j), i.max(j)));
+                }
+            } else if b_leaf || (!a_leaf && node_a.aabb.area() >= node_b.aabb.area()) {
+                let [left, right] = node_a.children;
+                self.stack.extend([(left, b), (right, b)]);
+            } else {
+                let [left, right] = node_b.children;
+                self.stack.extend([(a, left), (a, right)]);
+            }
+        }
+
+        out.sort_unstable();
+    }
+}
+
+fn cell_of(p: Vector3<f32>, cell: f32) -> [i32; 3] {
+    // Leave room for the neighbours of the outermost cells
+    let limit = CELL_OFFSET - 2;
+    let coord = |x: f32| ((x / cell).floor() as i32).clamp(-limit, limit);
+
+    [coord(p.x), coord(p.y), coord(p.z)]
+}
+
+fn pack(cell: [i32; 3]) -> u64 {
+    cell.iter()
+        .fold(0, |key, &c| (key << CELL_BITS) | (c + CELL_OFFSET) as u64)
+}
+
+fn unpack(key: u64) -> [i32; 3] {
+    let mask = (1 << CELL_BITS) - 1;
+    let coord = |shift: u32| ((key >> shift) & mask) as i32 - CELL_OFFSET;
+
+    [coord(2 * CELL_BITS), coord(CELL_BITS), coord(0)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKENDS: [&str; 5] = ["grid", "sweep-x", "sweep-y", "sweep-z", "tree"];
+
+    struct Lcg(u32);
+
+    impl Lcg {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+
+        fn vector(&mut self, scale: f32) -> Vector3<f32> {
+            Vector3::new(self.next(), self.next(), self.next()) * scale
+                - Vector3::new(0.5, 0.5, 0.5) * scale
+        }
+    }
+
+    // Wildly mixed radii, with a few huge boxes and some exact touches
+    fn mixed_boxes(rng: &mut Lcg) -> Vec<Aabb> {
+        let mut boxes: Vec<Aabb> = (0..500)
+            .map(|k| {
+                let radius = if k % 100 == 0 {
+                    20.0 + rng.next() * 30.0
+                } else {
+                    0.5 + rng.next() * rng.next() * 8.0
+                };
+                Aabb::around_sphere(rng.vector(100.0), radius)
+            })
+            .collect();
+        boxes.push(Aabb::around_sphere(Vector3::new(0.0, 0.0, 0.0), 1.0));
+        boxes.push(Aabb::around_sphere(Vector3::new(2.0, 0.0, 0.0), 1.0));
+        boxes
+    }
+
+    fn brute_force(boxes: &[Aabb]) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        BruteForce.find_pairs(boxes, &mut pairs);
+        pairs
+    }
+
+    #[test]
+    fn every_backend_matches_brute_force() {
+        let mut rng = Lcg(12345);
+        let boxes = mixed_boxes(&mut rng);
+        let expected = brute_force(&boxes);
+        assert!(!expected.is_empty());
+
+        for name in BACKENDS {
+            let mut broadphase = by_name(name).unwrap();
+            assert_eq!(broadphase.name(), name);
+
+            let mut pairs = Vec::new();
+            broadphase.find_pairs(&boxes, &mut pairs);
+            assert_eq!(pairs, expected, "{} disagrees with brute force", name);
+        }
+    }
+
+    #[test]
+    fn backends_stay_correct_as_boxes_move() {
+        let mut rng = Lcg(777);
+        let mut boxes = mixed_boxes(&mut rng);
+        let velocities: Vec<_> = boxes.iter().map(|_| rng.vector(4.0)).collect();
+
+        let mut backends: Vec<_> = BACKENDS.iter().map(|n| by_name(n).unwrap()).collect();
+        let mut pairs = Vec::new();
+
+        for frame in 0..30 {
+            for (b, v) in boxes.iter_mut().zip(&velocities) {
+                b.min += *v;
+                b.max += *v;
+            }
+            // Particles come and go, as after a merge or a reset
+            if frame == 15 {
+                boxes.truncate(400);
+            }
+
+            let expected = brute_force(&boxes);
+            for broadphase in backends.iter_mut() {
+                broadphase.find_pairs(&boxes, &mut pairs);
+                assert_eq!(
+                    pairs,
+                    expected,
+                    "{} wrong at frame {}",
+                    broadphase.name(),
+                    frame
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use cgmath::{InnerSpace, Vector3, Vector4};
+
+use crate::phys::Particle;
+
+/// A link between particles `a` and `b`, indices into
+/// `PhysicsWorld::particles`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Constraint {
+    pub a: usize,
+    pub b: usize,
+    /// Rest length of a spring, the length of a rod or the longest a rope
+    /// lets the pair get.
+    pub length: f32,
+    pub kind: ConstraintKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConstraintKind {
+    /// Hookean spring of `stiffness` in N/m, with `damping` in N s/m
+    /// against the rate it stretches.
+    Spring { stiffness: f32, damping: f32 },
+    /// Rigid rod keeping the pair exactly `length` apart.
+    Distance,
+    /// Slack while shorter than `length`, rigid once taut.
+    Rope,
+}
+
+impl Constraint {
+    pub fn spring(a: usize, b: usize, length: f32, stiffness: f32, damping: f32) -> Self {
+        Constraint {
+            a,
+            b,
+            length,
+            kind: ConstraintKind::Spring { stiffness, damping },
+        }
+    }
+
+    pub fn distance(a: usize, b: usize, length: f32) -> Self {
+        Constraint {
+            a,
+            b,
+            length,
+            kind: ConstraintKind::Distance,
+        }
+    }
+
+    pub fn rope(a: usize, b: usize, length: f32) -> Self {
+        Constraint {
+            a,
+            b,
+            length,
+            kind: ConstraintKind::Rope,
+        }
+    }
+
+    /// Energy stored in a stretched spring. Rods and ropes store none.
+    pub fn potential_energy(&self, particles: &[Particle]) -> f32 {
+        let ConstraintKind::Spring { stiffness, .. } = self.kind else {
+            return 0.0;
+        };
+        let d = particles[self.a].spatial_position() - particles[self.b].spatial_position();
+        let stretch = d.magnitude() - self.length;
+        0.5 * stiffness * stretch * stretch
+    }
+}
+
+/// Adds the pull of every spring to the accelerations of its ends.
+///
+/// Springs act as forces, so the integrator treats them like any other and
+/// they keep their energy. Rods and ropes are left to `solve`.
+pub fn accelerate(constraints: &[Constraint], particles: &mut [Particle]) {
+    for c in constraints {
+        let ConstraintKind::Spring { stiffness, damping } = c.kind else {
+            continue;
+        };
+        let (a, b) = (&particles[c.a], &particles[c.b]);
+        let d = a.spatial_position() - b.spatial_position();
+        let r = d.magnitude();
+        if r == 0.0 {
+            continue;
+        }
+        let n = d / r;
+
+        // Hooke's law, damped by the rate the spring stretches
+        let stretching = n.dot(a.three_velocity() - b.three_velocity());
+        let f = n * (-stiffness * (r - c.length) - damping * stretching);
+        let (m1, m2) = (a.mass, b.mass);
+        particles[c.a].acceleration += Vector4::new(0.0, f.x, f.y, f.z) / m1;
+        particles[c.b].acceleration -= Vector4::new(0.0, f.x, f.y, f.z) / m2;
+    }
+}
+
+/// Moves particles back onto their rods and ropes with position based
+/// dynamics, after the integrator moved them freely over `dt`.
+///
+/// Each iteration projects every violated constraint in turn, moving both
+/// ends in inverse proportion to their masses. The total correction of each
+/// particle is then added to its velocity, as the constraint force would
+/// have.
+pub fn solve(constraints: &[Constraint], particles: &mut [Particle], dt: f32, iterations: usize) {
+    if dt <= 0.0
+        || constraints
+            .iter()
+            .all(|c| matches!(c.kind, ConstraintKind::Spring { .. }))
+    {
+        return;
+    }
+
+    let unconstrained: Vec<Vector3<f32>> = particles.iter().map(|p| p.spatial_position()).collect();
+
+    for _ in 0..iterations {
+        for c in constraints {
+            let (a, b) = (&particles[c.a], &particles[c.b]);
+            let d = a.spatial_position() - b.spatial_position();
+            let r = d.magnitude();
+            let violation = r - c.length;
+            let taut = match c.kind {
+                ConstraintKind::Spring { .. } => false,
+                ConstraintKind::Distance => true,
+                ConstraintKind::Rope => violation > 0.0,
+            };
+            if !taut || r == 0.0 {
+                continue;
+            }
+
+            let (wa, wb) = (1.0 / a.mass, 1.0 / b.mass);
+            let correction = d * (-violation / (r * (wa + wb)));
+            let (xa, xb) = (a.spatial_position(), b.spatial_position());
+            particles[c.a].set_spatial_position(xa + correction * wa);
+            particles[c.b].set_spatial_position(xb - correction * wb);
+        }
+    }
+
+    for (p, x) in particles.iter_mut().zip(unconstrained) {
+        let correction = p.spatial_position() - x;
+        if correction != Vector3::new(0.0, 0.0, 0.0) {
+            p.set_proper_velocity(p.proper_velocity() + correction / dt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::part;
+    use crate::phys::PhysicsWorld;
+
+    fn momentum(world: &PhysicsWorld) -> Vector3<f32> {
+        world
+            .particles
+            .iter()
+            .map(|p| p.proper_velocity() * p.mass)
+            .sum()
+    }
+
+    #[test]
+    fn rod_keeps_its_length() {
+        let mut world = PhysicsWorld::new();
+        world.add_particle(part![0.0, 0.0, 0.0, 0.0; 0.0, 3.0, 0.0; 1.0; 0.1; 1.0, 1.0, 1.0]);
+        world.add_particle(part![0.0, 2.0, 0.0, 0.0; 0.0, -1.0, 1.0; 3.0; 0.1; 1.0, 1.0, 1.0]);
+        world.constraints.push(Constraint::distance(0, 1, 2.0));
+        let p0 = momentum(&world);
+
+        for _ in 0..500 {
+            world.update(0.01);
+        }
+
+        let (a, b) = (&world.particles[0], &world.particles[1]);
+        let r = (a.spatial_position() - b.spatial_position()).magnitude();
+        assert!((r - 2.0).abs() < 1e-4, "r = {}", r);
+        assert!((momentum(&world) - p0).magnitude() < 1e-2);
+        // Spinning, not stopped
+        assert!(world.kinetic_energy() > 1.0);
+    }
+
+    #[test]
+    fn rope_only_pulls() {
+        let mut world = PhysicsWorld::new();
+        world.add_particle(part![0.0, 0.0, 0.0, 0.0; 1.0; 0.1]);
+        world.add_particle(part![0.0, 1.0, 0.0, 0.0; 1.0, 0.0, 0.0; 1.0; 0.1; 1.0, 1.0, 1.0]);
+        world.constraints.push(Constraint::rope(0, 1, 3.0));
+
+        // Slack: coasting freely
+        world.update(1.0);
+        assert_eq!(world.particles[1].spatial_position().x, 2.0);
+        assert_eq!(
+            world.particles[0].three_velocity(),
+            Vector3::new(0.0, 0.0, 0.0)
+        );
+
+        // Taut: drags the other one along
+        for _ in 0..100 {
+            world.update(0.1);
+        }
+        let (a, b) = (&world.particles[0], &world.particles[1]);
+        assert!((b.spatial_position() - a.spatial_position()).magnitude() < 3.0 + 1e-4);
+        assert!(a.three_velocity().x > 0.0);
+    }
+
+    #[test]
+    fn spring_oscillates_and_damping_settles_it() {
+        let oscillate = |damping: f32| {
+            let mut world = PhysicsWorld::new();
+            world.add_particle(part![0.0, 0.0, 0.0, 0.0; 1.0; 0.1]);
+            world.add_particle(part![0.0, 1.5, 0.0, 0.0; 1.0; 0.1]);
+            world
+                .constraints
+                .push(Constraint::spring(0, 1, 1.0, 50.0, damping));
+
+            let energy = world.energy();
+            for _ in 0..1000 {
+                world.update(0.005);
+            }
+            (energy, world)
+        };
+
+        let (energy, world) = oscillate(0.0);
+        assert!((energy - 0.5 * 50.0 * 0.25).abs() < 1e-4);
+        assert!(
+            ((world.energy() - energy) / energy).abs() < 0.1,
+            "{} -> {}",
+            energy,
+            world.energy()
+        );
+
+        let (_, world) = oscillate(5.0);
+        let r = (world.particles[1].spatial_position() - world.particles[0].spatial_position())
+            .magnitude();
+        assert!((r - 1.0).abs() < 1e-3, "r = {}", r);
+        assert!(world.energy() < 1e-3);
+    }
+}
@@ -0,0 +1,251 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::phys::{C, Particle, coordinate_acceleration};
+
+/// Coulomb's constant `1 / (4 pi epsilon_0)` in SI units.
+pub const K: f32 = 8.987_552e9;
+
+/// Electrostatic interaction between every pair of charged particles.
+///
+/// Like gravity, distances are Plummer softened, `r^2 -> r^2 + softening^2`.
+/// The fields are added to `Particle::e_field` and `Particle::b_field`, so
+/// they act through the same Lorentz force as an external field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coulomb {
+    pub k: f32,
+    pub softening: f32,
+    /// Use the full Liénard-Wiechert field of each moving charge instead of
+    /// the electrostatic one, including its magnetic field.
+    ///
+    /// Retarded positions are found by running each source back along its
+    /// current velocity, which holds while light crosses the scene in less
+    /// than a step.
+    pub retarded: bool,
+}
+
+impl Default for Coulomb {
+    fn default() -> Self {
+        Coulomb {
+            k: K,
+            softening: 0.0,
+            retarded: false,
+        }
+    }
+}
+
+impl Coulomb {
+    /// Adds the field of every other charged particle to each particle's
+    /// `e_field` and `b_field`.
+    pub fn add_fields(&self, particles: &mut [Particle]) {
+        let charged: Vec<usize> = (0..particles.len())
+            .filter(|&j| particles[j].charge != 0.0)
+            .collect();
+        if charged.is_empty() {
+            return;
+        }
+
+        for i in 0..particles.len() {
+            let x = particles[i].spatial_position();
+            let (mut e, mut b) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+
+            for &j in charged.iter().filter(|&&j| j != i) {
+                let (e_j, b_j) = self.field_of(&particles[j], x);
+                e += e_j;
+                b += b_j;
+            }
+
+            particles[i].e_field += e;
+            particles[i].b_field += b;
+        }
+    }
+
+    /// E and B of `source` at `x`.
+    pub fn field_of(&self, source: &Particle, x: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        let eps2 = self.softening as f64 * self.softening as f64;
+        let kq = self.k as f64 * source.charge as f64;
+        let d = to_f64(x - source.spatial_position());
+
+        if !self.retarded {
+            let r2 = d.magnitude2() + eps2;
+            if r2 == 0.0 {
+                return (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+            }
+            let e = d * (kq / (r2 * r2.sqrt()));
+            return (to_f32(e), Vector3::new(0.0, 0.0, 0.0));
+        }
+
+        // The field seen at x now left the source `delay` ago, when it was
+        // `d + v delay` away and light had to cover exactly that distance:
+        // (c^2 - v^2) delay^2 - 2 (d.v) delay - d^2 = 0
+        let c = C as f64;
+        let v = to_f64(source.three_velocity());
+        let dv = d.dot(v);
+        let a = c * c - v.magnitude2();
+        let delay = (dv + (dv * dv + a * d.magnitude2()).sqrt()) / a;
+
+        let r_vec = d + v * delay;
+        let r = r_vec.magnitude();
+        if r == 0.0 {
+            return (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+        }
+        let n = r_vec / r;
+        // Softened the same way as the static field, which this is at rest
+        let r2 = r * r + eps2;
+        let (near_scale, far_scale) = (r / (r2 * r2.sqrt()), r / r2);
+
+        let beta = v / c;
+        let beta_dot = to_f64(
+            coordinate_acceleration(
+                source.proper_velocity(),
+                Vector3::new(
+                    source.acceleration.y,
+                    source.acceleration.z,
+                    source.acceleration.w,
+                ),
+            ) / C,
+        );
+        let kappa = 1.0 - n.dot(beta);
+        let k3 = kappa * kappa * kappa;
+
+        // Velocity (near) field plus acceleration (radiation) field
+        let near = (n - beta) * ((1.0 - beta.magnitude2()) * near_scale / k3);
+        let radiation = n.cross((n - beta).cross(beta_dot)) * (far_scale / (c * k3));
+        let e = (near + radiation) * kq;
+        let b = n.cross(e) / c;
+
+        (to_f32(e), to_f32(b))
+    }
+
+    /// Electrostatic energy of every pair of charges.
+    pub fn potential_energy(&self, particles: &[Particle]) -> f32 {
+        let eps2 = self.softening * self.softening;
+        let mut energy = 0.0;
+
+        for (i, a) in particles.iter().enumerate() {
+            if a.charge == 0.0 {
+                continue;
+            }
+            for b in &particles[i + 1..] {
+                let r2 = (b.spatial_position() - a.spatial_position()).magnitude2() + eps2;
+                if b.charge != 0.0 && r2 > 0.0 {
+                    energy += self.k * a.charge * b.charge / r2.sqrt();
+                }
+            }
+        }
+
+        energy
+    }
+}
+
+fn to_f64(v: Vector3<f32>) -> Vector3<f64> {
+    Vector3::new(v.x as f64, v.y as f64, v.z as f64)
+}
+
+fn to_f32(v: Vector3<f64>) -> Vector3<f32> {
+    Vector3::new(v.x as f32, v.y as f32, v.z as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::part;
+    use crate::phys::PhysicsWorld;
+
+    fn charged(mut p: Particle, charge: f32) -> Particle {
+        p.charge = charge;
+        p
+    }
+
+    #[test]
+    fn like_charges_repel_and_opposite_attract() {
+        for (charge, sign) in [(1e-3, 1.0), (-1e-3, -1.0)] {
+            let mut world = PhysicsWorld::new();
+            world.coulomb = Some(Coulomb::default());
+            world.add_particle(charged(part![0.0, 0.0, 0.0, 0.0; 2.0; 0.1], 1e-3));
+            world.add_particle(charged(part![0.0, 2.0, 0.0, 0.0; 1.0; 0.1], charge));
+
+            world.update(1e-6);
+
+            // k q1 q2 / r^2 over each mass
+            let force = K * 1e-3 * charge / 4.0;
+            let a = [
+                world.particles[0].acceleration.y,
+                world.particles[1].acceleration.y,
+            ];
+            assert!((a[0] + force / 2.0).abs() < 1e-4 * force.abs(), "{:?}", a);
+            assert!((a[1] - force).abs() < 1e-4 * force.abs(), "{:?}", a);
+            assert_eq!(a[1].signum(), sign);
+        }
+    }
+
+    #[test]
+    fn moving_charge_field_is_flattened() {
+        // A charge in uniform motion along x, seen from beside where it is
+        // now: E is gamma times the static field and B = v x E / c^2
+        let gamma: f32 = 5.0 / 3.0;
+        let speed = 0.8 * C;
+        let source = charged(
+            part![0.0, 0.0, 0.0, 0.0; speed, 0.0, 0.0; 1.0; 0.1; 1.0, 1.0, 1.0],
+            1e-6,
+        );
+        let coulomb = Coulomb {
+            retarded: true,
+            ..Default::default()
+        };
+
+        let (e, b) = coulomb.field_of(&source, Vector3::new(0.0, 2.0, 0.0));
+        let expected = gamma * K * 1e-6 / 4.0;
+        assert!(
+            (e - Vector3::new(0.0, expected, 0.0)).magnitude() < 1e-4 * expected,
+            "{:?}",
+            e
+        );
+        assert!(
+            (b.z - speed * expected / (C * C)).abs() < 1e-4 * b.z.abs(),
+            "{:?}",
+            b
+        );
+
+        // Ahead of it the field is weaker by gamma^2
+        let (e, _) = coulomb.field_of(&source, Vector3::new(2.0, 0.0, 0.0));
+        let expected = K * 1e-6 / 4.0 / (gamma * gamma);
+        assert!((e.x - expected).abs() < 1e-4 * expected, "{:?}", e);
+    }
+
+    #[test]
+    fn static_and_retarded_fields_agree_at_rest() {
+        let source = charged(part![0.0, 1.0, 2.0, 3.0; 1.0; 0.1], -2e-6);
+        let x = Vector3::new(4.0, -1.0, 0.5);
+
+        let coulomb = Coulomb {
+            softening: 0.5,
+            ..Default::default()
+        };
+        let retarded = Coulomb {
+            retarded: true,
+            ..coulomb
+        };
+
+        let (e, b) = coulomb.field_of(&source, x);
+        let (e_r, b_r) = retarded.field_of(&source, x);
+        assert!((e - e_r).magnitude() < 1e-5 * e.magnitude());
+        assert_eq!(b, Vector3::new(0.0, 0.0, 0.0));
+        assert!(b_r.magnitude() < 1e-6 * e.magnitude() / C);
+    }
+
+    #[test]
+    fn softened_pair_energy() {
+        let coulomb = Coulomb {
+            k: 1.0,
+            softening: 3.0,
+            retarded: false,
+        };
+        let particles = [
+            charged(part![0.0, 0.0, 0.0, 0.0; 1.0; 0.1], 2.0),
+            charged(part![0.0, 4.0, 0.0, 0.0; 1.0; 0.1], -3.0),
+            part![0.0, 1.0, 1.0, 1.0; 1.0; 0.1],
+        ];
+
+        assert_eq!(coulomb.potential_energy(&particles), -6.0 / 5.0);
+    }
+}
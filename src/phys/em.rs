@@ -0,0 +1,188 @@
+use cgmath::{Matrix4, Vector3, Vector4};
+
+use crate::phys::{C, Particle};
+
+/// An electric and magnetic field filling space, possibly changing in time.
+///
+/// Any `Fn(x, t) -> (E, B)` closure is a field, for anything the provided
+/// ones don't cover.
+pub trait EmField: Send {
+    /// E in V/m and B in T at position `x` and time `t`.
+    fn at(&self, x: Vector3<f32>, t: f32) -> (Vector3<f32>, Vector3<f32>);
+}
+
+impl<F> EmField for F
+where
+    F: Fn(Vector3<f32>, f32) -> (Vector3<f32>, Vector3<f32>) + Send,
+{
+    fn at(&self, x: Vector3<f32>, t: f32) -> (Vector3<f32>, Vector3<f32>) {
+        self(x, t)
+    }
+}
+
+/// The same E and B everywhere, forever.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UniformField {
+    pub e: Vector3<f32>,
+    pub b: Vector3<f32>,
+}
+
+impl EmField for UniformField {
+    fn at(&self, _x: Vector3<f32>, _t: f32) -> (Vector3<f32>, Vector3<f32>) {
+        (self.e, self.b)
+    }
+}
+
+/// Uniform E and B both scaled by `cos(angular_frequency * t)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OscillatingField {
+    pub e: Vector3<f32>,
+    pub b: Vector3<f32>,
+    pub angular_frequency: f32,
+}
+
+impl EmField for OscillatingField {
+    fn at(&self, _x: Vector3<f32>, t: f32) -> (Vector3<f32>, Vector3<f32>) {
+        let scale = (self.angular_frequency * t).cos();
+        (self.e * scale, self.b * scale)
+    }
+}
+
+/// The electromagnetic field tensor `F^mu_nu`, acting on contravariant
+/// 4-vectors `(ct, x, y, z)` with a (+, -, -, -) metric.
+pub fn field_tensor(e: Vector3<f32>, b: Vector3<f32>) -> Matrix4<f32> {
+    let e = e / C;
+    // Matrix4::new takes columns
+    Matrix4::new(
+        0.0, e.x, e.y, e.z, //
+        e.x, 0.0, -b.z, b.y, //
+        e.y, b.z, 0.0, -b.x, //
+        e.z, -b.y, b.x, 0.0,
+    )
+}
+
+/// `dU/dtau = (q / m) F U`, the covariant Lorentz force on `particle` per
+/// unit rest mass.
+pub fn lorentz_force(particle: &Particle, e: Vector3<f32>, b: Vector3<f32>) -> Vector4<f32> {
+    field_tensor(e, b) * particle.velocity * (particle.charge / particle.mass)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::part;
+    use crate::phys::{PhysicsWorld, integrator};
+    use cgmath::InnerSpace;
+
+    fn charged(mut p: Particle, charge: f32) -> Particle {
+        p.charge = charge;
+        p
+    }
+
+    #[test]
+    fn tensor_gives_lorentz_force() {
+        let p = charged(
+            part![0.0, 0.0, 0.0, 0.0; 0.3 * C, 0.4 * C, 0.0; 2.0; 1.0; 1.0, 1.0, 1.0],
+            3.0,
+        );
+        let (e, b) = (Vector3::new(1e8, -2e8, 5e7), Vector3::new(0.5, 0.2, -1.0));
+
+        let du_dtau = lorentz_force(&p, e, b);
+        let v = p.three_velocity();
+        let expected = (e + v.cross(b)) * (p.gamma() * p.charge / p.mass);
+
+        let spatial = Vector3::new(du_dtau.y, du_dtau.z, du_dtau.w);
+        assert!((spatial - expected).magnitude() < 1e-5 * expected.magnitude());
+        // Only E does work
+        let power = e.dot(v) * p.gamma() * p.charge / p.mass / C;
+        assert!((du_dtau.x - power).abs() < 1e-5 * power.abs());
+    }
+
+    #[test]
+    fn relativistic_cyclotron_orbit() {
+        // q B / m = 1 and gamma 2, so the orbit has radius gamma v and
+        // period 2 pi gamma
+        let speed = C * 0.75f32.sqrt();
+        let gamma = 2.0;
+
+        let mut world = PhysicsWorld::new();
+        world.set_integrator(integrator::by_name("boris").unwrap());
+        world.em_field = Some(Box::new(UniformField {
+            e: Vector3::new(0.0, 0.0, 0.0),
+            b: Vector3::new(0.0, 0.0, 1.0),
+        }));
+        world.add_particle(charged(
+            part![0.0, 0.0, 0.0, 0.0; 0.0, speed, 0.0; 1.0; 1.0; 1.0, 1.0, 1.0],
+            1.0,
+        ));
+
+        let steps = 2000;
+        let period = std::f32::consts::TAU * gamma;
+        let mut furthest: f32 = 0.0;
+        for _ in 0..steps {
+            world.update(period / steps as f32);
+            furthest = furthest.max(world.particles[0].spatial_position().magnitude());
+        }
+
+        let p = &world.particles[0];
+        let diameter = 2.0 * gamma * speed;
+        assert!(
+            (furthest - diameter).abs() < 1e-3 * diameter,
+            "{}",
+            furthest
+        );
+        assert!(p.spatial_position().magnitude() < 1e-2 * diameter);
+        // A magnetic field does no work
+        assert!((p.gamma() - gamma).abs() < 1e-4, "gamma = {}", p.gamma());
+    }
+
+    #[test]
+    fn crossed_fields_drift_at_e_over_b() {
+        // q / m = 1, so the gyration period is 2 pi and the drift is E x B / B^2
+        let mut world = PhysicsWorld::new();
+        world.set_integrator(integrator::by_name("boris").unwrap());
+        world.em_field = Some(Box::new(UniformField {
+            e: Vector3::new(0.0, 2.0, 0.0),
+            b: Vector3::new(0.0, 0.0, 1.0),
+        }));
+        world.add_particle(charged(part![0.0, 0.0, 0.0, 0.0; 1.0; 0.1], 1.0));
+
+        let periods = 10.0;
+        let steps = 10000;
+        let duration = std::f32::consts::TAU * periods;
+        for _ in 0..steps {
+            world.update(duration / steps as f32);
+        }
+
+        let drift = world.particles[0].spatial_position() / duration;
+        assert!((drift.x - 2.0).abs() < 1e-2, "{:?}", drift);
+        assert!(drift.y.abs() < 1e-2 && drift.z.abs() < 1e-6, "{:?}", drift);
+    }
+
+    #[test]
+    fn removing_the_field_stops_the_gyration() {
+        let mut world = PhysicsWorld::new();
+        world.set_integrator(integrator::by_name("boris").unwrap());
+        world.em_field = Some(Box::new(UniformField {
+            e: Vector3::new(0.0, 0.0, 0.0),
+            b: Vector3::new(0.0, 0.0, 1.0),
+        }));
+        world.add_particle(charged(
+            part![0.0, 0.0, 0.0, 0.0; 1.0, 0.0, 0.0; 1.0; 1.0; 1.0, 1.0, 1.0],
+            1.0,
+        ));
+        for _ in 0..100 {
+            world.update(0.01);
+        }
+
+        world.em_field = None;
+        let before = world.particles[0].three_velocity();
+        for _ in 0..100 {
+            world.update(0.01);
+        }
+
+        let p = &world.particles[0];
+        assert_eq!(p.b_field, Vector3::new(0.0, 0.0, 0.0));
+        assert!((p.three_velocity() - before).magnitude() < 1e-6);
+    }
+}
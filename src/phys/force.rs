@@ -0,0 +1,167 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::phys::Particle;
+
+/// An external force acting on every particle, registered with
+/// `PhysicsWorld::add_force_field`.
+///
+/// Any `Fn(x, v, t) -> a` closure over position, 3-velocity and time is a
+/// force field, for anything the provided ones don't cover.
+pub trait ForceField: Send {
+    /// Force per unit rest mass on `particle` at time `t`, the same units as
+    /// `Particle::acceleration`.
+    fn acceleration(&self, particle: &Particle, t: f32) -> Vector3<f32>;
+}
+
+impl<F> ForceField for F
+where
+    F: Fn(Vector3<f32>, Vector3<f32>, f32) -> Vector3<f32> + Send,
+{
+    fn acceleration(&self, particle: &Particle, t: f32) -> Vector3<f32> {
+        self(particle.spatial_position(), particle.three_velocity(), t)
+    }
+}
+
+/// The same acceleration everywhere.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Uniform {
+    pub acceleration: Vector3<f32>,
+}
+
+impl ForceField for Uniform {
+    fn acceleration(&self, _particle: &Particle, _t: f32) -> Vector3<f32> {
+        self.acceleration
+    }
+}
+
+/// Pushes away from `center` with `strength * r^exponent`, or pulls towards
+/// it when `strength` is negative. An exponent of -2 is an inverse square
+/// law, 1 a spring.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Radial {
+    pub center: Vector3<f32>,
+    pub strength: f32,
+    pub exponent: f32,
+}
+
+impl ForceField for Radial {
+    fn acceleration(&self, particle: &Particle, _t: f32) -> Vector3<f32> {
+        let d = particle.spatial_position() - self.center;
+        let r = d.magnitude();
+        if r == 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+
+        d * (self.strength * r.powf(self.exponent) / r)
+    }
+}
+
+/// Swirls particles around the line through `center` along `axis`, with a
+/// push of `strength` at every distance, anticlockwise seen from the tip of
+/// `axis`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vortex {
+    pub center: Vector3<f32>,
+    pub axis: Vector3<f32>,
+    pub strength: f32,
+}
+
+impl ForceField for Vortex {
+    fn acceleration(&self, particle: &Particle, _t: f32) -> Vector3<f32> {
+        let axis = self.axis.normalize();
+        let d = particle.spatial_position() - self.center;
+        let swirl = axis.cross(d);
+        let r = swirl.magnitude();
+        if r == 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+
+        swirl * (self.strength / r)
+    }
+}
+
+/// Slows particles down with `-(linear + quadratic * |v|) v`, viscous drag
+/// at low speed and air resistance at high speed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Drag {
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl ForceField for Drag {
+    fn acceleration(&self, particle: &Particle, _t: f32) -> Vector3<f32> {
+        let v = particle.three_velocity();
+        -v * (self.linear + self.quadratic * v.magnitude())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::part;
+    use crate::phys::{PhysicsWorld, integrator};
+    use cgmath::Vector4;
+
+    #[test]
+    fn world_gravity_drops_particles() {
+        let mut world = PhysicsWorld::new();
+        world.set_integrator(integrator::by_name("leapfrog").unwrap());
+        world.gravity = Vector4::new(0.0, 0.0, -9.81, 0.0);
+        world.add_particle(part![0.0, 0.0, 100.0, 0.0; 1.0; 0.1]);
+        let energy = world.energy();
+
+        for _ in 0..100 {
+            world.update(0.01);
+        }
+
+        let p = &world.particles[0];
+        assert!((p.spatial_position().y - (100.0 - 0.5 * 9.81)).abs() < 1e-3);
+        assert!((p.three_velocity().y + 9.81).abs() < 1e-3);
+        assert!((world.energy() - energy).abs() < 1e-2, "{}", world.energy());
+    }
+
+    #[test]
+    fn drag_reaches_terminal_velocity() {
+        let mut world = PhysicsWorld::new();
+        world.gravity = Vector4::new(0.0, 0.0, -10.0, 0.0);
+        world.add_force_field(Drag {
+            linear: 0.0,
+            quadratic: 0.1,
+        });
+        world.add_particle(part![0.0, 0.0, 0.0, 0.0; 1.0; 0.1]);
+
+        for _ in 0..2000 {
+            world.update(0.01);
+        }
+
+        // g = k v^2
+        let v = world.particles[0].three_velocity().y;
+        assert!((v + 10.0).abs() < 1e-3, "v = {}", v);
+    }
+
+    #[test]
+    fn fields_and_closures_add_up() {
+        let mut world = PhysicsWorld::new();
+        world.add_force_field(Radial {
+            center: Vector3::new(0.0, 0.0, 0.0),
+            strength: -2.0,
+            exponent: 1.0,
+        });
+        world.add_force_field(Vortex {
+            center: Vector3::new(0.0, 0.0, 0.0),
+            axis: Vector3::new(0.0, 0.0, 2.0),
+            strength: 3.0,
+        });
+        world.add_force_field(Uniform {
+            acceleration: Vector3::new(0.0, 0.0, 1.0),
+        });
+        world.add_force_field(|x: Vector3<f32>, _v: Vector3<f32>, t: f32| x * (t + 0.5));
+        world.add_particle(part![0.0, 2.0, 0.0, 0.0; 1.0; 0.1]);
+
+        world.update(1.0);
+
+        // Spring -2 x, swirl 3 along +y, 1 along +z and x / 2 at t = 0
+        let a = world.particles[0].acceleration;
+        assert_eq!(a, Vector4::new(0.0, -3.0, 3.0, 1.0));
+    }
+}
@@ -0,0 +1,469 @@
+use cgmath::{InnerSpace, Vector3, Vector4, Zero};
+
+use crate::phys::Particle;
+
+/// Gravitational constant in SI units.
+pub const G: f32 = 6.674e-11;
+
+/// Newtonian attraction between every pair of particles.
+///
+/// Distances are softened with a Plummer sphere, `r^2 -> r^2 + softening^2`,
+/// so close encounters don't blow up and the potential stays finite.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NBodyGravity {
+    pub g: f32,
+    pub softening: f32,
+    /// Barnes-Hut opening angle. Octree cells that look smaller than this
+    /// from a particle act as a single mass; 0 sums every pair directly.
+    pub theta: f32,
+}
+
+impl Default for NBodyGravity {
+    fn default() -> Self {
+        NBodyGravity {
+            g: G,
+            softening: 0.0,
+            theta: 0.0,
+        }
+    }
+}
+
+impl NBodyGravity {
+    /// Adds the pull of every other particle to each `acceleration`.
+    pub fn accelerate(&self, particles: &mut [Particle]) {
+        self.accelerate_with(particles, &mut Octree::new());
+    }
+
+    /// Like `accelerate`, building the Barnes-Hut tree in `tree` so its
+    /// storage is reused from one call to the next.
+    pub fn accelerate_with(&self, particles: &mut [Particle], tree: &mut Octree) {
+        if self.theta <= 0.0 {
+            self.accelerate_directly(particles);
+            return;
+        }
+
+        tree.build(particles);
+        for (i, p) in particles.iter_mut().enumerate() {
+            let (a, _) = tree.field_at(i, p.spatial_position(), self);
+            p.acceleration += Vector4::new(0.0, a.x, a.y, a.z);
+        }
+    }
+
+    fn accelerate_directly(&self, particles: &mut [Particle]) {
+        let eps2 = self.softening * self.softening;
+        let positions: Vec<Vector3<f32>> = particles.iter().map(|p| p.spatial_position()).collect();
+        let mut accels = vec![Vector3::new(0.0, 0.0, 0.0); particles.len()];
+
+        // Each pair once, with equal and opposite pulls
+        for i in 0..particles.len() {
+            for j in i + 1..particles.len() {
+                let d = positions[j] - positions[i];
+                let r2 = d.magnitude2() + eps2;
+                if r2 == 0.0 {
+                    continue;
+                }
+
+                let f = d * (self.g / (r2 * r2.sqrt()));
+                accels[i] += f * particles[j].mass;
+                accels[j] -= f * particles[i].mass;
+            }
+        }
+
+        for (p, a) in particles.iter_mut().zip(accels) {
+            p.acceleration += Vector4::new(0.0, a.x, a.y, a.z);
+        }
+    }
+
+    /// Total potential energy of the softened pair interactions, with the
+    /// same approximation as the forces.
+    pub fn potential_energy(&self, particles: &[Particle]) -> f32 {
+        if self.theta <= 0.0 {
+            return self.potential_energy_directly(particles);
+        }
+
+        let mut tree = Octree::new();
+        tree.build(particles);
+
+        // Every pair is seen from both ends
+        let energy: f32 = particles
+            .iter()
+            .enumerate()
+            .map(|(i, p)| p.mass * tree.field_at(i, p.spatial_position(), self).1)
+            .sum();
+        0.5 * energy
+    }
+
+    fn potential_energy_directly(&self, particles: &[Particle]) -> f32 {
+        let eps2 = self.softening * self.softening;
+        let mut energy = 0.0;
+
+        for (i, a) in particles.iter().enumerate() {
+            for b in &particles[i + 1..] {
+                let r2 = (b.spatial_position() - a.spatial_position()).magnitude2() + eps2;
+                if r2 > 0.0 {
+                    energy -= self.g * a.mass * b.mass / r2.sqrt();
+                }
+            }
+        }
+
+        energy
+    }
+}
+
+/// Cells holding at most this many particles aren't split further.
+const LEAF_SIZE: usize = 8;
+
+/// Cells this deep are leaves however crowded, so coincident particles can't
+/// split forever.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+struct Cell {
+    center: Vector3<f32>,
+    half: f32,
+    mass: f32,
+    center_of_mass: Vector3<f32>,
+    // Range of `Octree::order` holding the particles inside
+    start: usize,
+    end: usize,
+    // Index of the first of eight consecutive children, 0 for a leaf
+    children: usize,
+}
+
+/// Octree over particle positions for Barnes-Hut gravity. Each cell knows
+/// the total mass and centre of mass of the particles inside, so a distant
+/// cell can stand in for all of them.
+#[derive(Clone, Debug, Default)]
+pub struct Octree {
+    cells: Vec<Cell>,
+    // Particle indices, grouped so every cell's particles are contiguous
+    order: Vec<usize>,
+    positions: Vec<Vector3<f32>>,
+    masses: Vec<f32>,
+    // Cells still to visit while walking the tree for one particle
+    stack: Vec<usize>,
+}
+
+impl Octree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rebuilds the tree around the current particle positions.
+    pub fn build(&mut self, particles: &[Particle]) {
+        self.cells.clear();
+        self.order.clear();
+        self.order.extend(0..particles.len());
+        self.positions.clear();
+        self.positions
+            .extend(particles.iter().map(|p| p.spatial_position()));
+        self.masses.clear();
+        self.masses.extend(particles.iter().map(|p| p.mass));
+
+        if particles.is_empty() {
+            return;
+        }
+
+        // Smallest cube around every particle
+        let first = self.positions[0];
+        let (min, max) = self.positions.iter().fold((first, first), |(min, max), x| {
+            (
+                Vector3::new(min.x.min(x.x), min.y.min(x.y), min.z.min(x.z)),
+                Vector3::new(max.x.max(x.x), max.y.max(x.y), max.z.max(x.z)),
+            )
+        });
+        let size = max - min;
+        let half = 0.5 * size.x.max(size.y).max(size.z);
+
+        self.cells.push(Cell {
+            center: (min + max) * 0.5,
+            half,
+            mass: 0.0,
+            center_of_mass: Vector3::zero(),
+            start: 0,
+            end: particles.len(),
+            children: 0,
+        });
+        self.split(0, 0);
+    }
+
+    /// Splits `cell` into octants until the leaves are small enough, filling
+    /// in the mass of every cell on the way back up.
+    fn split(&mut self, cell: usize, depth: usize) {
+        let Cell {
+            center,
+            half,
+            start,
+            end,
+            ..
+        } = self.cells[cell];
+
+        if end - start <= LEAF_SIZE || depth >= MAX_DEPTH {
+            let (mass, moment) =
+                self.order[start..end]
+                    .iter()
+                    .fold((0.0, Vector3::zero()), |(mass, moment), &i| {
+                        (
+                            mass + self.masses[i],
+                            moment + self.positions[i] * self.masses[i],
+                        )
+                    });
+            self.cells[cell].mass = mass;
+            self.cells[cell].center_of_mass = if mass > 0.0 { moment / mass } else { center };
+            return;
+        }
+
+        let positions = &self.positions;
+        let octant = |i: usize| {
+            let x = positions[i];
+            (x.x >= center.x) as usize
+                | ((x.y >= center.y) as usize) << 1
+                | ((x.z >= center.z) as usize) << 2
+        };
+        self.order[start..end].sort_unstable_by_key(|&i| octant(i));
+
+        let children = self.cells.len();
+        let mut child_start = start;
+        for k in 0..8 {
+            let child_end = child_start
+                + self.order[child_start..end]
+                    .iter()
+                    .take_while(|&&i| octant(i) == k)
+                    .count();
+            let sign = |bit: usize| if k & bit == 0 { -0.5 } else { 0.5 };
+            self.cells.push(Cell {
+                center: center + Vector3::new(sign(1), sign(2), sign(4)) * half,
+                half: 0.5 * half,
+                mass: 0.0,
+                center_of_mass: Vector3::zero(),
+                start: child_start,
+                end: child_end,
+                children: 0,
+            });
+            child_start = child_end;
+        }
+        self.cells[cell].children = children;
+
+        let mut mass = 0.0;
+        let mut moment = Vector3::zero();
+        for child in children..children + 8 {
+            if self.cells[child].start < self.cells[child].end {
+                self.split(child, depth + 1);
+                mass += self.cells[child].mass;
+                moment += self.cells[child].center_of_mass * self.cells[child].mass;
+            }
+        }
+        self.cells[cell].mass = mass;
+        self.cells[cell].center_of_mass = if mass > 0.0 { moment / mass } else { center };
+    }
+
+    /// Acceleration and potential per unit mass at `x`, the position of
+    /// particle `skip`, from every other particle in the tree.
+    fn field_at(
+        &mut self,
+        skip: usize,
+        x: Vector3<f32>,
+        gravity: &NBodyGravity,
+    ) -> (Vector3<f32>, f32) {
+        let eps2 = gravity.softening * gravity.softening;
+        let theta2 = gravity.theta * gravity.theta;
+        let mut accel = Vector3::zero();
+        let mut potential = 0.0;
+
+        let mut pull = |d: Vector3<f32>, mass: f32| {
+            let r2 = d.magnitude2() + eps2;
+            if r2 > 0.0 {
+                let r = r2.sqrt();
+                accel += d * (gravity.g * mass / (r2 * r));
+                potential -= gravity.g * mass / r;
+            }
+        };
+
+        self.stack.clear();
+        if !self.cells.is_empty() {
+            self.stack.push(0);
+        }
+        while let Some(k) = self.stack.pop() {
+            let cell = self.cells[k];
+            if cell.start == cell.end {
+                continue;
+            }
+
+            // Far enough away that the whole cell acts as one mass. A cell
+            // around `x` always opens so a particle never pulls on itself.
+            let d = cell.center_of_mass - x;
+            let width = 2.0 * cell.half;
+            let offset = x - cell.center;
+            let inside = offset.x.abs().max(offset.y.abs()).max(offset.z.abs()) <= cell.half;
+            if !inside && width * width < theta2 * d.magnitude2() {
+                pull(d, cell.mass);
+            } else if cell.children == 0 {
+                for &j in &self.order[cell.start..cell.end] {
+                    if j != skip {
+                        pull(self.positions[j] - x, self.masses[j]);
+                    }
+                }
+            } else {
+                self.stack.extend(cell.children..cell.children + 8);
+            }
+        }
+
+        (accel, potential)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::part;
+    use crate::phys::{PhysicsWorld, integrator};
+
+    // A light body on a circular orbit of radius 1 around a heavy one, with
+    // G M = 1 so the period is 2 pi
+    fn orbit() -> PhysicsWorld {
+        let mut world = PhysicsWorld::new();
+        world.n_body = Some(NBodyGravity {
+            g: 1.0,
+            softening: 0.0,
+            theta: 0.0,
+        });
+        world.add_particle(part![0.0, 0.0, 0.0, 0.0; 1.0; 0.01]);
+        world.add_particle(part![
+            0.0, 1.0, 0.0, 0.0;
+            0.0, 1.0, 0.0;
+            1e-6; 0.01;
+            1.0, 1.0, 1.0
+        ]);
+        world
+    }
+
+    #[test]
+    fn pull_follows_inverse_square_law() {
+        let gravity = NBodyGravity {
+            g: 2.0,
+            softening: 0.0,
+            theta: 0.0,
+        };
+        let mut particles = vec![
+            part![0.0, 0.0, 0.0, 0.0; 3.0; 0.1],
+            part![0.0, 2.0, 0.0, 0.0; 5.0; 0.1],
+        ];
+        gravity.accelerate(&mut particles);
+
+        assert!((particles[0].acceleration.y - 2.0 * 5.0 / 4.0).abs() < 1e-6);
+        assert!((particles[1].acceleration.y + 2.0 * 3.0 / 4.0).abs() < 1e-6);
+        assert!((gravity.potential_energy(&particles) + 2.0 * 15.0 / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn softening_keeps_coincident_particles_finite() {
+        let gravity = NBodyGravity {
+            g: 1.0,
+            softening: 0.5,
+            theta: 0.0,
+        };
+        let mut particles = vec![
+            part![0.0, 0.0, 0.0, 0.0; 1.0; 0.1],
+            part![0.0, 0.0, 0.0, 0.0; 1.0; 0.1],
+        ];
+        gravity.accelerate(&mut particles);
+
+        assert_eq!(particles[0].acceleration, Vector4::new(0.0, 0.0, 0.0, 0.0));
+        assert_eq!(gravity.potential_energy(&particles), -2.0);
+    }
+
+    #[test]
+    fn circular_orbit_comes_back_around() {
+        let mut world = orbit();
+        world.set_integrator(integrator::by_name("leapfrog").unwrap());
+        let energy = world.energy();
+
+        let steps = 1000;
+        let dt = std::f32::consts::TAU / steps as f32;
+        for _ in 0..steps {
+            world.update(dt);
+        }
+
+        let x = world.particles[1].spatial_position() - world.particles[0].spatial_position();
+        assert!(
+            (x - Vector3::new(1.0, 0.0, 0.0)).magnitude() < 1e-2,
+            "{:?}",
+            x
+        );
+        assert!(
+            ((world.energy() - energy) / energy).abs() < 1e-3,
+            "{} -> {}",
+            energy,
+            world.energy()
+        );
+    }
+
+    // Uniform random points in a cube, from a fixed LCG seed
+    fn random_cloud(count: usize) -> Vec<Particle> {
+        let mut state = 12345u64;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 40) as f32 / (1u64 << 24) as f32
+        };
+
+        (0..count)
+            .map(|_| part![0.0, next() * 100.0, next() * 100.0, next() * 100.0; 0.5 + next(); 0.1])
+            .collect()
+    }
+
+    #[test]
+    fn barnes_hut_matches_direct_summation() {
+        let direct = NBodyGravity {
+            g: 1.0,
+            softening: 0.1,
+            theta: 0.0,
+        };
+        let mut exact = random_cloud(2000);
+        direct.accelerate(&mut exact);
+        let exact_energy = direct.potential_energy(&exact);
+
+        let mut tree = Octree::new();
+        let mut previous_error = 0.0;
+        for theta in [0.3, 0.5, 1.0] {
+            let barnes_hut = NBodyGravity { theta, ..direct };
+            let mut approx = random_cloud(2000);
+            // Twice through the same tree, to check it rebuilds cleanly
+            barnes_hut.accelerate_with(&mut approx, &mut tree);
+            for p in approx.iter_mut() {
+                p.acceleration = Vector4::zero();
+            }
+            barnes_hut.accelerate_with(&mut approx, &mut tree);
+
+            // RMS force error relative to the RMS force
+            let (error, norm) = exact
+                .iter()
+                .zip(&approx)
+                .fold((0.0, 0.0), |(e, n), (a, b)| {
+                    (
+                        e + (a.acceleration - b.acceleration).magnitude2(),
+                        n + a.acceleration.magnitude2(),
+                    )
+                });
+            let error = (error / norm).sqrt();
+            let energy_error =
+                ((barnes_hut.potential_energy(&approx) - exact_energy) / exact_energy).abs();
+
+            assert!(
+                error < 0.05 * theta * theta,
+                "theta {}: force error {}",
+                theta,
+                error
+            );
+            assert!(
+                energy_error < 0.01,
+                "theta {}: energy error {}",
+                theta,
+                energy_error
+            );
+            assert!(error >= previous_error, "theta {}: {}", theta, error);
+            previous_error = error;
+        }
+    }
+}
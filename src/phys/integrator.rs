@@ -0,0 +1,263 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::phys::{Particle, coordinate_acceleration, gamma_from_proper, spatial};
+
+/// Refreshes `Particle::acceleration` for the given particle states at time `t`.
+pub type AccelFn<'a> = dyn FnMut(&mut [Particle], f32) + 'a;
+
+/// A time-stepping scheme for particle motion.
+///
+/// All schemes integrate the proper velocity `u = gamma * v` with
+/// `du/dt = acceleration` and `dx/dt = u / gamma`, which reduces to Newton in
+/// the slow limit and can never push a particle past C.
+pub trait Integrator: Send {
+    fn name(&self) -> &'static str;
+
+    /// Advances positions and velocities of `particles` from `t` to `t + dt`.
+    /// `accel` is called whenever the scheme needs accelerations for a new state.
+    fn step(&self, particles: &mut [Particle], t: f32, dt: f32, accel: &mut AccelFn);
+}
+
+/// Looks up an integrator by the name used in scene files and on the command line.
+pub fn by_name(name: &str) -> Option<Box<dyn Integrator>> {
+    match name {
+        "euler" => Some(Box::new(SemiImplicitEuler)),
+        "verlet" => Some(Box::new(VelocityVerlet)),
+        "rk4" => Some(Box::new(Rk4)),
+        "leapfrog" => Some(Box::new(RelativisticLeapfrog)),
+        "boris" => Some(Box::new(Boris)),
+        _ => None,
+    }
+}
+
+/// Kick then drift with the updated velocity.
+pub struct SemiImplicitEuler;
+
+/// Position update with the current acceleration, velocity update with the
+/// average of the old and new ones.
+pub struct VelocityVerlet;
+
+/// Classic fourth order Runge-Kutta on the (x, u) state of every particle.
+pub struct Rk4;
+
+/// Drift-kick-drift leapfrog in coordinate time: half a drift at the
+/// coordinate velocity `u / gamma`, a full kick of the proper velocity `u`,
+/// then the other half drift. Every particle shares the same `dt`, and the
+/// scheme is time-reversible.
+pub struct RelativisticLeapfrog;
+
+/// Leapfrog with the relativistic Boris push for the kick: half the
+/// non-magnetic kick, an exact-length rotation about `Particle::b_field`,
+/// then the other half. The rotation never changes `|u|`, so gyration keeps
+/// its energy however long the run.
+pub struct Boris;
+
+impl Integrator for SemiImplicitEuler {
+    fn name(&self) -> &'static str {
+        "euler"
+    }
+
+    fn step(&self, particles: &mut [Particle], t: f32, dt: f32, accel: &mut AccelFn) {
+        accel(particles, t);
+
+        for p in particles.iter_mut() {
+            p.set_proper_velocity(p.proper_velocity() + spatial(p.acceleration) * dt);
+            p.set_spatial_position(p.spatial_position() + p.three_velocity() * dt);
+        }
+    }
+}
+
+impl Integrator for VelocityVerlet {
+    fn name(&self) -> &'static str {
+        "verlet"
+    }
+
+    fn step(&self, particles: &mut [Particle], t: f32, dt: f32, accel: &mut AccelFn) {
+        accel(particles, t);
+
+        let old_accels: Vec<Vector3<f32>> =
+            particles.iter().map(|p| spatial(p.acceleration)).collect();
+
+        for (p, a) in particles.iter_mut().zip(&old_accels) {
+            let dv_dt = coordinate_acceleration(p.proper_velocity(), *a);
+            let x = p.spatial_position() + p.three_velocity() * dt + dv_dt * (0.5 * dt * dt);
+            p.set_spatial_position(x);
+        }
+
+        accel(particles, t + dt);
+
+        for (p, a) in particles.iter_mut().zip(&old_accels) {
+            let a_avg = (*a + spatial(p.acceleration)) * 0.5;
+            p.set_proper_velocity(p.proper_velocity() + a_avg * dt);
+        }
+    }
+}
+
+impl Integrator for Rk4 {
+    fn name(&self) -> &'static str {
+        "rk4"
+    }
+
+    fn step(&self, particles: &mut [Particle], t: f32, dt: f32, accel: &mut AccelFn) {
+        let x0: Vec<Vector3<f32>> = particles.iter().map(Particle::spatial_position).collect();
+        let u0: Vec<Vector3<f32>> = particles.iter().map(Particle::proper_velocity).collect();
+
+        // Stages are evaluated on a scratch copy so the real particles only
+        // change once, at the end of the step.
+        let mut scratch = particles.to_vec();
+
+        let mut derivative = |x: &[Vector3<f32>], u: &[Vector3<f32>], t: f32| {
+            for ((p, x), u) in scratch.iter_mut().zip(x).zip(u) {
+                p.set_spatial_position(*x);
+                p.set_proper_velocity(*u);
+            }
+
+            accel(&mut scratch, t);
+
+            scratch
+                .iter()
+                .map(|p| (p.three_velocity(), spatial(p.acceleration)))
+                .collect::<Vec<_>>()
+        };
+
+        let advance = |k: &[(Vector3<f32>, Vector3<f32>)], h: f32| {
+            let x: Vec<_> = x0.iter().zip(k).map(|(x, k)| x + k.0 * h).collect();
+            let u: Vec<_> = u0.iter().zip(k).map(|(u, k)| u + k.1 * h).collect();
+            (x, u)
+        };
+
+        let k1 = derivative(&x0, &u0, t);
+        let (x, u) = advance(&k1, dt * 0.5);
+        let k2 = derivative(&x, &u, t + dt * 0.5);
+        let (x, u) = advance(&k2, dt * 0.5);
+        let k3 = derivative(&x, &u, t + dt * 0.5);
+        let (x, u) = advance(&k3, dt);
+        let k4 = derivative(&x, &u, t + dt);
+
+        for (i, p) in particles.iter_mut().enumerate() {
+            let dx = k1[i].0 + k2[i].0 * 2.0 + k3[i].0 * 2.0 + k4[i].0;
+            let du = k1[i].1 + k2[i].1 * 2.0 + k3[i].1 * 2.0 + k4[i].1;
+
+            p.set_spatial_position(x0[i] + dx * (dt / 6.0));
+            p.set_proper_velocity(u0[i] + du * (dt / 6.0));
+        }
+    }
+}
+
+impl Integrator for RelativisticLeapfrog {
+    fn name(&self) -> &'static str {
+        "leapfrog"
+    }
+
+    fn step(&self, particles: &mut [Particle], t: f32, dt: f32, accel: &mut AccelFn) {
+        let half_drift = |p: &mut Particle| {
+            let u = p.proper_velocity();
+            let v = u / gamma_from_proper(u);
+            p.set_spatial_position(p.spatial_position() + v * (0.5 * dt));
+        };
+
+        particles.iter_mut().for_each(half_drift);
+
+        accel(particles, t + 0.5 * dt);
+
+        for p in particles.iter_mut() {
+            p.set_proper_velocity(p.proper_velocity() + spatial(p.acceleration) * dt);
+            half_drift(p);
+        }
+    }
+}
+
+impl Integrator for Boris {
+    fn name(&self) -> &'static str {
+        "boris"
+    }
+
+    fn step(&self, particles: &mut [Particle], t: f32, dt: f32, accel: &mut AccelFn) {
+        let half_drift = |p: &mut Particle| {
+            let u = p.proper_velocity();
+            let v = u / gamma_from_proper(u);
+            p.set_spatial_position(p.spatial_position() + v * (0.5 * dt));
+        };
+
+        particles.iter_mut().for_each(half_drift);
+
+        accel(particles, t + 0.5 * dt);
+
+        for p in particles.iter_mut() {
+            let q_over_m = p.charge / p.mass;
+            let u = p.proper_velocity();
+
+            // The magnetic part of the acceleration is handled by the rotation
+            let magnetic = p.three_velocity().cross(p.b_field) * q_over_m;
+            let kick = (spatial(p.acceleration) - magnetic) * (0.5 * dt);
+
+            let u_minus = u + kick;
+            let t_vec = p.b_field * (0.5 * q_over_m * dt / gamma_from_proper(u_minus));
+            let s_vec = t_vec * (2.0 / (1.0 + t_vec.magnitude2()));
+            let u_prime = u_minus + u_minus.cross(t_vec);
+            let u_plus = u_minus + u_prime.cross(s_vec);
+
+            p.set_proper_velocity(u_plus + kick);
+            half_drift(p);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::part;
+    use crate::phys::C;
+    use cgmath::Vector4;
+
+    // Unit harmonic oscillator, a = -x, slow enough to be Newtonian.
+    fn oscillator_energy_drift(integrator: &dyn Integrator) -> f32 {
+        let mut particles = vec![part![0.0, 1.0, 0.0, 0.0; 1; 0.1]];
+        let energy = |p: &Particle| {
+            0.5 * (p.proper_velocity().magnitude2() + p.spatial_position().magnitude2())
+        };
+        let e0 = energy(&particles[0]);
+
+        let mut spring = |ps: &mut [Particle], _t: f32| {
+            for p in ps.iter_mut() {
+                let x = p.spatial_position();
+                p.acceleration = Vector4::new(0.0, -x.x, -x.y, -x.z);
+            }
+        };
+
+        let dt = 0.01;
+        for i in 0..1000 {
+            integrator.step(&mut particles, i as f32 * dt, dt, &mut spring);
+        }
+
+        ((energy(&particles[0]) - e0) / e0).abs()
+    }
+
+    #[test]
+    fn integrators_conserve_oscillator_energy() {
+        for name in ["euler", "verlet", "rk4", "leapfrog", "boris"] {
+            let integrator = by_name(name).unwrap();
+            let drift = oscillator_energy_drift(integrator.as_ref());
+            assert!(drift < 1e-2, "{} drifted by {}", name, drift);
+        }
+
+        assert!(oscillator_energy_drift(&Rk4) < 1e-4);
+    }
+
+    #[test]
+    fn leapfrog_never_exceeds_c() {
+        let mut particles = vec![part![0.0, 0.0, 0.0, 0.0; 1; 0.1]];
+        let mut push = |ps: &mut [Particle], _t: f32| {
+            for p in ps.iter_mut() {
+                p.acceleration = Vector4::new(0.0, 1e9, 0.0, 0.0);
+            }
+        };
+
+        for i in 0..100 {
+            RelativisticLeapfrog.step(&mut particles, i as f32, 1.0, &mut push);
+        }
+
+        let v = particles[0].three_velocity().magnitude();
+        assert!(v < C && v > 0.99 * C, "v = {}", v);
+    }
+}
@@ -0,0 +1,145 @@
+use serde::Deserialize;
+
+/// Index into `PhysicsWorld::materials`. Material 0 is the default one.
+pub type MaterialId = usize;
+
+/// How the values of two touching materials are combined into one.
+///
+/// When the two materials ask for different rules, the one later in this list
+/// wins, so a `Max` material always gets its way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Combine {
+    #[default]
+    Average,
+    Min,
+    Multiply,
+    Max,
+}
+
+impl Combine {
+    pub fn apply(self, a: f32, b: f32) -> f32 {
+        match self {
+            Combine::Average => 0.5 * (a + b),
+            Combine::Min => a.min(b),
+            Combine::Multiply => a * b,
+            Combine::Max => a.max(b),
+        }
+    }
+}
+
+/// Surface properties used when resolving contacts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    /// Fraction of the normal velocity kept after a bounce.
+    pub restitution: f32,
+    /// Friction coefficient below which sliding contacts stick.
+    pub static_friction: f32,
+    /// Friction coefficient while sliding.
+    pub dynamic_friction: f32,
+    pub restitution_combine: Combine,
+    pub friction_combine: Combine,
+}
+
+impl Material {
+    /// Perfectly elastic and frictionless.
+    pub const DEFAULT: Material = Material {
+        restitution: 1.0,
+        static_friction: 0.0,
+        dynamic_friction: 0.0,
+        restitution_combine: Combine::Average,
+        friction_combine: Combine::Average,
+    };
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::DEFAULT
+    }
+}
+
+/// Combined properties of a pair of touching materials.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContactMaterial {
+    pub restitution: f32,
+    pub static_friction: f32,
+    pub dynamic_friction: f32,
+}
+
+impl Material {
+    pub fn combine(&self, other: &Material) -> ContactMaterial {
+        let restitution = self.restitution_combine.max(other.restitution_combine);
+        let friction = self.friction_combine.max(other.friction_combine);
+
+        ContactMaterial {
+            restitution: restitution.apply(self.restitution, other.restitution),
+            static_friction: friction.apply(self.static_friction, other.static_friction),
+            dynamic_friction: friction.apply(self.dynamic_friction, other.dynamic_friction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combine_rules() {
+        let a = Material {
+            restitution: 0.2,
+            static_friction: 0.8,
+            dynamic_friction: 0.6,
+            ..Default::default()
+        };
+        let b = Material {
+            restitution: 0.6,
+            static_friction: 0.4,
+            dynamic_friction: 0.2,
+            ..Default::default()
+        };
+
+        let average = a.combine(&b);
+        assert!((average.restitution - 0.4).abs() < 1e-6);
+        assert!((average.static_friction - 0.6).abs() < 1e-6);
+        assert!((average.dynamic_friction - 0.4).abs() < 1e-6);
+
+        let rules = [
+            (Combine::Min, 0.2),
+            (Combine::Max, 0.6),
+            (Combine::Multiply, 0.12),
+        ];
+        for (rule, expected) in rules {
+            let a = Material {
+                restitution_combine: rule,
+                ..a
+            };
+            assert!(
+                (a.combine(&b).restitution - expected).abs() < 1e-6,
+                "{:?}",
+                rule
+            );
+            assert!(
+                (b.combine(&a).restitution - expected).abs() < 1e-6,
+                "{:?}",
+                rule
+            );
+        }
+    }
+
+    #[test]
+    fn stronger_rule_wins() {
+        let min = Material {
+            friction_combine: Combine::Min,
+            static_friction: 0.1,
+            ..Default::default()
+        };
+        let max = Material {
+            friction_combine: Combine::Max,
+            static_friction: 0.9,
+            ..Default::default()
+        };
+
+        assert_eq!(min.combine(&max).static_friction, 0.9);
+        assert_eq!(max.combine(&min).static_friction, 0.9);
+    }
+}
@@ -0,0 +1,407 @@
+use cgmath::{InnerSpace, Vector3, Vector4};
+
+use crate::phys::Particle;
+use crate::phys::broadphase::{Aabb, Broadphase, SpatialHash};
+
+/// Index into the species table of `PairPotentials`. Particles are species 0
+/// unless told otherwise.
+pub type SpeciesId = usize;
+
+/// A short-range interaction between two particles, as a function of their
+/// distance `r` only.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PairPotential {
+    /// `4 epsilon ((sigma / r)^12 - (sigma / r)^6)`, deepest at
+    /// `2^(1/6) sigma`.
+    LennardJones { epsilon: f32, sigma: f32 },
+    /// `depth ((1 - exp(-width (r - r0)))^2 - 1)`, deepest at `r0`.
+    Morse { depth: f32, width: f32, r0: f32 },
+    /// Purely repulsive `epsilon (sigma / r)^exponent`.
+    SoftSphere {
+        epsilon: f32,
+        sigma: f32,
+        exponent: f32,
+    },
+}
+
+impl PairPotential {
+    pub fn energy(&self, r: f32) -> f32 {
+        match *self {
+            PairPotential::LennardJones { epsilon, sigma } => {
+                let s6 = (sigma / r).powi(6);
+                4.0 * epsilon * (s6 * s6 - s6)
+            }
+            PairPotential::Morse { depth, width, r0 } => {
+                let e = (-width * (r - r0)).exp();
+                depth * ((1.0 - e) * (1.0 - e) - 1.0)
+            }
+            PairPotential::SoftSphere {
+                epsilon,
+                sigma,
+                exponent,
+            } => epsilon * (sigma / r).powf(exponent),
+        }
+    }
+
+    /// `-dU/dr`, positive when the pair pushes apart.
+    pub fn force(&self, r: f32) -> f32 {
+        match *self {
+            PairPotential::LennardJones { epsilon, sigma } => {
+                let s6 = (sigma / r).powi(6);
+                24.0 * epsilon * (2.0 * s6 * s6 - s6) / r
+            }
+            PairPotential::Morse { depth, width, r0 } => {
+                let e = (-width * (r - r0)).exp();
+                -2.0 * depth * width * e * (1.0 - e)
+            }
+            PairPotential::SoftSphere {
+                epsilon,
+                sigma,
+                exponent,
+            } => exponent * epsilon * (sigma / r).powf(exponent) / r,
+        }
+    }
+}
+
+/// A potential cut off at `cutoff`, shifted so its energy goes to zero there
+/// rather than jumping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PairInteraction {
+    pub potential: PairPotential,
+    pub cutoff: f32,
+}
+
+impl PairInteraction {
+    pub fn energy(&self, r: f32) -> f32 {
+        if r >= self.cutoff {
+            return 0.0;
+        }
+        self.potential.energy(r) - self.potential.energy(self.cutoff)
+    }
+
+    pub fn force(&self, r: f32) -> f32 {
+        if r >= self.cutoff {
+            return 0.0;
+        }
+        self.potential.force(r)
+    }
+}
+
+/// Short-range forces between particles, looked up by the species of each
+/// particle in the pair.
+///
+/// Pairs are found through a Verlet neighbour list: every pair closer than
+/// its cutoff plus `skin`, found with a broadphase and only rebuilt once some
+/// particle has moved more than half the skin.
+pub struct PairPotentials {
+    /// Extra distance kept in the neighbour list so it stays valid for a few
+    /// steps.
+    pub skin: f32,
+    // Symmetric, indexed by the species of both particles
+    table: Vec<Vec<Option<PairInteraction>>>,
+    broadphase: Box<dyn Broadphase>,
+    boxes: Vec<Aabb>,
+    candidates: Vec<(usize, usize)>,
+    neighbours: Vec<(usize, usize)>,
+    // Positions when the neighbour list was last built
+    built_at: Vec<Vector3<f32>>,
+}
+
+impl Default for PairPotentials {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PairPotentials {
+    pub fn new() -> Self {
+        PairPotentials {
+            skin: 0.3,
+            table: Vec::new(),
+            broadphase: Box::new(SpatialHash::new()),
+            boxes: Vec::new(),
+            candidates: Vec::new(),
+            neighbours: Vec::new(),
+            built_at: Vec::new(),
+        }
+    }
+
+    /// Sets the interaction between species `a` and `b`, either way round.
+    pub fn set(&mut self, a: SpeciesId, b: SpeciesId, potential: PairPotential, cutoff: f32) {
+        let species = self.table.len().max(a.max(b) + 1);
+        self.table.resize(species, Vec::new());
+        for row in self.table.iter_mut() {
+            row.resize(species, None);
+        }
+
+        let interaction = Some(PairInteraction { potential, cutoff });
+        self.table[a][b] = interaction;
+        self.table[b][a] = interaction;
+        // Force a rebuild, the list may be missing pairs
+        self.built_at.clear();
+    }
+
+    pub fn get(&self, a: SpeciesId, b: SpeciesId) -> Option<&PairInteraction> {
+        self.table.get(a)?.get(b)?.as_ref()
+    }
+
+    /// Pairs in the current neighbour list, as indices into the particles.
+    pub fn neighbours(&self) -> &[(usize, usize)] {
+        &self.neighbours
+    }
+
+    /// Longest cutoff of any interaction.
+    fn reach(&self) -> f32 {
+        self.table
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|i| i.cutoff)
+            .fold(0.0, f32::max)
+    }
+
+    /// Adds the pair forces to each particle's `acceleration`.
+    pub fn accelerate(&mut self, particles: &mut [Particle]) {
+        self.update_neighbours(particles);
+
+        for &(i, j) in &self.neighbours {
+            let (a, b) = (&particles[i], &particles[j]);
+            let Some(interaction) = self.get(a.species, b.species) else {
+                continue;
+            };
+
+            let d = a.spatial_position() - b.spatial_position();
+            let r = d.magnitude();
+            if r == 0.0 || r >= interaction.cutoff {
+                continue;
+            }
+
+            let f = d * (interaction.force(r) / r);
+            let (m1, m2) = (a.mass, b.mass);
+            particles[i].acceleration += Vector4::new(0.0, f.x, f.y, f.z) / m1;
+            particles[j].acceleration -= Vector4::new(0.0, f.x, f.y, f.z) / m2;
+        }
+    }
+
+    /// Total energy of every interacting pair.
+    pub fn potential_energy(&self, particles: &[Particle]) -> f32 {
+        // Found afresh, as the list may be out of date
+        let mut pairs = Vec::new();
+        let mut boxes = Vec::new();
+        candidate_pairs(
+            particles,
+            self.reach(),
+            &mut SpatialHash::new(),
+            &mut boxes,
+            &mut pairs,
+        );
+
+        pairs
+            .iter()
+            .filter_map(|&(i, j)| {
+                let (a, b) = (&particles[i], &particles[j]);
+                let interaction = self.get(a.species, b.species)?;
+                let r = (a.spatial_position() - b.spatial_position()).magnitude();
+                Some(interaction.energy(r))
+            })
+            .sum()
+    }
+
+    fn update_neighbours(&mut self, particles: &[Particle]) {
+        let limit = 0.5 * self.skin;
+        let stale = self.built_at.len() != particles.len()
+            || particles
+                .iter()
+                .zip(&self.built_at)
+                .any(|(p, x)| (p.spatial_position() - x).magnitude2() > limit * limit);
+        if !stale {
+            return;
+        }
+
+        candidate_pairs(
+            particles,
+            self.reach() + self.skin,
+            self.broadphase.as_mut(),
+            &mut self.boxes,
+            &mut self.candidates,
+        );
+
+        self.neighbours.clear();
+        for &(i, j) in &self.candidates {
+            let (a, b) = (&particles[i], &particles[j]);
+            let Some(interaction) = self.get(a.species, b.species) else {
+                continue;
+            };
+            let range = interaction.cutoff + self.skin;
+            if (a.spatial_position() - b.spatial_position()).magnitude2() < range * range {
+                self.neighbours.push((i, j));
+            }
+        }
+
+        self.built_at.clear();
+        self.built_at
+            .extend(particles.iter().map(|p| p.spatial_position()));
+    }
+}
+
+/// Every pair of particles that might be within `range` of each other.
+fn candidate_pairs(
+    particles: &[Particle],
+    range: f32,
+    broadphase: &mut dyn Broadphase,
+    boxes: &mut Vec<Aabb>,
+    out: &mut Vec<(usize, usize)>,
+) {
+    boxes.clear();
+    boxes.extend(
+        particles
+            .iter()
+            .map(|p| Aabb::around_sphere(p.spatial_position(), 0.5 * range)),
+    );
+    broadphase.find_pairs(boxes, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::part;
+    use crate::phys::{PhysicsWorld, integrator};
+
+    const POTENTIALS: [PairPotential; 3] = [
+        PairPotential::LennardJones {
+            epsilon: 2.0,
+            sigma: 1.5,
+        },
+        PairPotential::Morse {
+            depth: 3.0,
+            width: 1.2,
+            r0: 1.4,
+        },
+        PairPotential::SoftSphere {
+            epsilon: 1.0,
+            sigma: 1.0,
+            exponent: 12.0,
+        },
+    ];
+
+    #[test]
+    fn force_is_minus_energy_gradient() {
+        for potential in POTENTIALS {
+            for r in [1.0, 1.3, 1.7, 2.5] {
+                let h = 1e-3;
+                let slope = (potential.energy(r + h) - potential.energy(r - h)) / (2.0 * h);
+                let f = potential.force(r);
+                assert!(
+                    (f + slope).abs() < 1e-2 * (1.0 + f.abs()),
+                    "{:?} at {}: {} vs {}",
+                    potential,
+                    r,
+                    f,
+                    -slope
+                );
+            }
+        }
+
+        let lj = POTENTIALS[0];
+        let r_min = 2f32.powf(1.0 / 6.0) * 1.5;
+        assert!(lj.force(r_min).abs() < 1e-4);
+        assert!((lj.energy(r_min) + 2.0).abs() < 1e-5);
+    }
+
+    // Particles on a jittered lattice, alternately species 0 and 1
+    fn lattice(side: usize, spacing: f32) -> Vec<Particle> {
+        let mut state = 99u64;
+        let mut jitter = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 40) as f32 / (1u64 << 24) as f32 - 0.5) * 0.2 * spacing
+        };
+
+        let mut particles = Vec::new();
+        for k in 0..side * side * side {
+            let (x, y, z) = (k % side, (k / side) % side, k / (side * side));
+            let mut p = part![
+                0.0,
+                x as f32 * spacing + jitter(),
+                y as f32 * spacing + jitter(),
+                z as f32 * spacing + jitter();
+                1.0 + (k % 2) as f32; 0.1
+            ];
+            p.species = k % 2;
+            particles.push(p);
+        }
+        particles
+    }
+
+    fn mixture() -> PairPotentials {
+        let mut potentials = PairPotentials::new();
+        potentials.set(0, 0, POTENTIALS[0], 3.0);
+        potentials.set(0, 1, POTENTIALS[1], 2.5);
+        // Species 1 doesn't interact with itself
+        potentials
+    }
+
+    #[test]
+    fn neighbour_list_matches_all_pairs() {
+        let mut potentials = mixture();
+        assert!(potentials.get(1, 1).is_none());
+        assert_eq!(potentials.get(1, 0), potentials.get(0, 1));
+
+        let mut particles = lattice(6, 1.6);
+        potentials.accelerate(&mut particles);
+
+        let mut expected = lattice(6, 1.6);
+        for i in 0..expected.len() {
+            for j in 0..expected.len() {
+                let (a, b) = (&expected[i], &expected[j]);
+                let Some(interaction) = potentials.get(a.species, b.species) else {
+                    continue;
+                };
+                let d = a.spatial_position() - b.spatial_position();
+                let r = d.magnitude();
+                if i != j && r < interaction.cutoff {
+                    let f = d * (interaction.force(r) / r / a.mass);
+                    expected[i].acceleration += Vector4::new(0.0, f.x, f.y, f.z);
+                }
+            }
+        }
+
+        for (p, q) in particles.iter().zip(&expected) {
+            let error = (p.acceleration - q.acceleration).magnitude();
+            assert!(
+                error < 1e-4 * (1.0 + q.acceleration.magnitude()),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn molecular_dynamics_conserves_energy() {
+        let mut world = PhysicsWorld::new();
+        world.set_integrator(integrator::by_name("verlet").unwrap());
+        world.pair_potentials = Some(mixture());
+        for p in lattice(5, 1.6) {
+            world.add_particle(p);
+        }
+
+        let energy = world.energy();
+        let mut rebuilds = 0;
+        let mut last = Vec::new();
+        for _ in 0..2000 {
+            world.update(1e-3);
+            let neighbours = world.pair_potentials.as_ref().unwrap().neighbours();
+            if neighbours != last.as_slice() {
+                rebuilds += 1;
+                last = neighbours.to_vec();
+            }
+        }
+
+        let drift = ((world.energy() - energy) / energy).abs();
+        assert!(drift < 1e-3, "{} -> {}", energy, world.energy());
+        assert!(world.kinetic_energy() > 0.0);
+        // The list lasts for many steps
+        assert!(rebuilds < 200, "{} rebuilds", rebuilds);
+    }
+}
@@ -0,0 +1,275 @@
+use cgmath::{InnerSpace, Vector3, Vector4};
+
+use crate::phys::constraint::Constraint;
+use crate::phys::material::MaterialId;
+use crate::phys::{C, Particle, PhysicsWorld};
+
+/// Triangles between particles, drawn as one deformable mesh in place of the
+/// particles' own spheres.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Surface {
+    /// Indices into `PhysicsWorld::particles`, anticlockwise seen from
+    /// outside.
+    pub triangles: Vec<[usize; 3]>,
+}
+
+/// A box-shaped grid of particles held together by springs, a sheet of cloth
+/// when one of `counts` is 1 and a soft body otherwise.
+///
+/// Structural springs join neighbours along each axis, shear springs join
+/// them across the diagonals, and bend springs skip a particle along each
+/// axis so the lattice resists folding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lattice {
+    pub center: Vector3<f32>,
+    /// Particles along x, y and z.
+    pub counts: [usize; 3],
+    /// Rest distance between neighbours.
+    pub spacing: f32,
+    pub velocity: Vector3<f32>,
+    /// Mass of each particle.
+    pub mass: f32,
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub material: MaterialId,
+    /// Structural and shear spring stiffness, in N/m.
+    pub stiffness: f32,
+    pub damping: f32,
+    /// Bend spring stiffness, 0 for cloth that folds freely.
+    pub bend_stiffness: f32,
+}
+
+impl Lattice {
+    /// Adds the particles, springs and outer surface to `world`. Particles
+    /// are appended in x, then y, then z order.
+    pub fn build(&self, world: &mut PhysicsWorld) {
+        let first = world.particles.len();
+        let [nx, ny, nz] = self.counts;
+        let index = |[x, y, z]: [usize; 3]| first + x + nx * (y + ny * z);
+        let cells = || {
+            (0..nz).flat_map(move |z| (0..ny).flat_map(move |y| (0..nx).map(move |x| [x, y, z])))
+        };
+
+        let half =
+            Vector3::new((nx - 1) as f32, (ny - 1) as f32, (nz - 1) as f32) * self.spacing / 2.0;
+        let v = self.velocity;
+        for [x, y, z] in cells() {
+            let offset = Vector3::new(x as f32, y as f32, z as f32) * self.spacing - half;
+            let p = self.center + offset;
+            let mut particle = Particle::new(
+                Vector4::new(0.0, p.x, p.y, p.z),
+                Vector4::new(C, v.x, v.y, v.z),
+                self.mass,
+                self.radius,
+                self.color,
+                0.0,
+            );
+            particle.material = self.material;
+            world.add_particle(particle);
+        }
+
+        // Every offset towards +x, +y or +z once, so each pair is joined once
+        let mut links: Vec<([isize; 3], f32)> = Vec::new();
+        for dz in -1..=1isize {
+            for dy in -1..=1isize {
+                for dx in -1..=1isize {
+                    let d = [dx, dy, dz];
+                    if d.iter().find(|&&c| c != 0).is_some_and(|&c| c > 0) {
+                        links.push((d, self.stiffness));
+                    }
+                }
+            }
+        }
+        if self.bend_stiffness > 0.0 {
+            links.extend([[2, 0, 0], [0, 2, 0], [0, 0, 2]].map(|d| (d, self.bend_stiffness)));
+        }
+
+        for a in cells() {
+            for &(d, stiffness) in &links {
+                let b = [0, 1, 2].map(|k| a[k] as isize + d[k]);
+                if (0..3).any(|k| b[k] < 0 || b[k] >= self.counts[k] as isize) {
+                    continue;
+                }
+                let b = b.map(|c| c as usize);
+
+                let (i, j) = (index(a), index(b));
+                let length = (world.particles[i].spatial_position()
+                    - world.particles[j].spatial_position())
+                .magnitude();
+                world
+                    .constraints
+                    .push(Constraint::spring(i, j, length, stiffness, self.damping));
+            }
+        }
+
+        world.surfaces.push(Surface {
+            triangles: self.surface(first),
+        });
+    }
+
+    /// Triangles over the outside of the lattice, or both sides of a sheet,
+    /// for particles starting at index `first`.
+    fn surface(&self, first: usize) -> Vec<[usize; 3]> {
+        let [nx, ny, _] = self.counts;
+        let index = |c: [usize; 3]| first + c[0] + nx * (c[1] + ny * c[2]);
+        let mut triangles = Vec::new();
+
+        // Each face is normal to `axis` and spanned by the other two, `u`
+        // then `v`, which wind anticlockwise seen from its positive side
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            if self.counts[u] < 2 || self.counts[v] < 2 {
+                continue;
+            }
+
+            let last = self.counts[axis] - 1;
+            let sides: &[usize] = if last == 0 { &[0] } else { &[0, last] };
+            for &side in sides {
+                for i in 0..self.counts[u] - 1 {
+                    for j in 0..self.counts[v] - 1 {
+                        let corner = |di: usize, dj: usize| {
+                            let mut c = [0; 3];
+                            c[axis] = side;
+                            c[u] = i + di;
+                            c[v] = j + dj;
+                            index(c)
+                        };
+                        let (a, b, c, d) = (corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1));
+                        // The low side faces the other way
+                        if side == 0 && last > 0 {
+                            triangles.extend([[a, c, b], [a, d, c]]);
+                        } else {
+                            triangles.extend([[a, b, c], [a, c, d]]);
+                        }
+                    }
+                }
+            }
+        }
+
+        triangles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phys::constraint::ConstraintKind;
+
+    fn lattice(counts: [usize; 3]) -> Lattice {
+        Lattice {
+            center: Vector3::new(0.0, 0.0, 0.0),
+            counts,
+            spacing: 1.0,
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+            mass: 1.0,
+            radius: 0.3,
+            color: [1.0, 1.0, 1.0],
+            material: 0,
+            stiffness: 100.0,
+            damping: 0.0,
+            bend_stiffness: 10.0,
+        }
+    }
+
+    #[test]
+    fn cloth_has_structural_shear_and_bend_springs() {
+        let mut world = PhysicsWorld::new();
+        lattice([3, 1, 3]).build(&mut world);
+
+        assert_eq!(world.particles.len(), 9);
+        let stiffness = |k: f32| {
+            world
+                .constraints
+                .iter()
+                .filter(|c| {
+                    c.kind
+                        == ConstraintKind::Spring {
+                            stiffness: k,
+                            damping: 0.0,
+                        }
+                })
+                .count()
+        };
+        // 12 edges and 2 diagonals in each of the 4 cells, then 3 bends each way
+        assert_eq!(stiffness(100.0), 12 + 8);
+        assert_eq!(stiffness(10.0), 6);
+        // Centred on the origin
+        let mean: Vector3<f32> = world
+            .particles
+            .iter()
+            .map(|p| p.spatial_position())
+            .sum::<Vector3<f32>>()
+            / 9.0;
+        assert!(mean.magnitude() < 1e-6);
+
+        assert_eq!(world.surfaces[0].triangles.len(), 8);
+        assert_eq!(world.energy(), 0.0);
+    }
+
+    #[test]
+    fn soft_body_surface_is_closed_and_faces_outwards() {
+        let mut world = PhysicsWorld::new();
+        let body = Lattice {
+            center: Vector3::new(1.0, 2.0, 3.0),
+            ..lattice([2, 3, 4])
+        };
+        body.build(&mut world);
+
+        let triangles = &world.surfaces[0].triangles;
+        // Two triangles per cell, on both sides of 2 + 6 + 3 cells
+        assert_eq!(triangles.len(), 2 * 2 * (2 + 6 + 3));
+
+        // Every edge is shared by exactly two triangles, walked once each way
+        let mut edges = std::collections::HashMap::new();
+        for t in triangles {
+            for k in 0..3 {
+                *edges.entry((t[k], t[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &n) in &edges {
+            assert_eq!(n, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+
+        for t in triangles {
+            let [a, b, c] = t.map(|i| world.particles[i].spatial_position());
+            let normal = (b - a).cross(c - a);
+            assert!(normal.dot(a - body.center) > 0.0, "{:?}", t);
+        }
+    }
+
+    #[test]
+    fn dropped_soft_body_keeps_its_shape() {
+        let mut world = PhysicsWorld::new();
+        world.gravity = Vector4::new(0.0, 0.0, -9.81, 0.0);
+        world
+            .planes
+            .push(crate::plane![0.0, -3.0, 0.0; 20.0, 0.0, 20.0; [1.0, 1.0, 1.0]]);
+        Lattice {
+            damping: 1.0,
+            stiffness: 500.0,
+            bend_stiffness: 100.0,
+            ..lattice([3, 3, 3])
+        }
+        .build(&mut world);
+
+        for _ in 0..2000 {
+            world.update(0.002);
+        }
+
+        // Resting on the floor, squashed a little by its own weight
+        let ys: Vec<f32> = world
+            .particles
+            .iter()
+            .map(|p| p.spatial_position().y)
+            .collect();
+        let low = ys.iter().cloned().fold(f32::INFINITY, f32::min);
+        let high = ys.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        assert!((low + 3.0 - 0.3).abs() < 0.05, "low = {}", low);
+        assert!(
+            high - low > 1.8 && high - low < 2.0,
+            "height = {}",
+            high - low
+        );
+    }
+}
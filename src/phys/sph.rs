@@ -0,0 +1,452 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3, Vector4};
+
+use crate::phys::broadphase::{Aabb, Broadphase, SpatialHash};
+use crate::phys::{C, Particle};
+
+/// Density of water at zero pressure, in kg/m^3.
+pub const WATER_DENSITY: f32 = 1000.0;
+/// Speed of sound `Sph::new` starts from, in m/s.
+pub const SOUND_SPEED: f32 = 20.0;
+/// Stiffness of the Tait equation for water.
+pub const TAIT_EXPONENT: f32 = 7.0;
+/// Artificial viscosity `Sph::new` starts from.
+pub const ARTIFICIAL_VISCOSITY: f32 = 0.05;
+
+/// Smoothed-particle hydrodynamics: every particle is a parcel of a weakly
+/// compressible fluid, pushed around by the pressure of its neighbours.
+///
+/// Densities are summed over neighbours with a cubic spline kernel reaching
+/// out to twice the `smoothing_length`, found through a spatial hash grid.
+/// Pressure follows the Tait equation of state
+/// `p = B ((rho / rho_0)^exponent - 1)` with `B = rho_0 c^2 / exponent`,
+/// clamped at zero so free surfaces don't pull the fluid apart, and
+/// viscosity is Monaghan's artificial viscosity, which conserves momentum.
+pub struct Sph {
+    /// Kernel width `h`. Around 1.3 times the particle spacing gives each
+    /// particle enough neighbours.
+    pub smoothing_length: f32,
+    /// Density at zero pressure, in kg/m^3.
+    pub rest_density: f32,
+    /// Speed of sound at the rest density. Ten times the fastest flow keeps
+    /// density changes to about a percent.
+    pub sound_speed: f32,
+    /// Stiffness of the Tait equation, 7 for water.
+    pub exponent: f32,
+    /// Artificial viscosity coefficient, around 0.01 to 0.1.
+    pub viscosity: f32,
+    /// Sum densities in the lab frame, where moving fluid is Lorentz
+    /// contracted, and take pressure from the rest frame density. Pressure
+    /// forces are divided by the specific enthalpy, as in the special
+    /// relativistic SPH of Chow and Monaghan, with its change over a step
+    /// neglected.
+    pub relativistic: bool,
+    /// Colour particles by their density, from deep blue where the fluid is
+    /// thin to white where it is compressed.
+    pub color_by_density: bool,
+    grid: SpatialHash,
+    boxes: Vec<Aabb>,
+    pairs: Vec<(usize, usize)>,
+    // Lab frame density, rest frame density and pressure of each particle
+    lab_densities: Vec<f32>,
+    densities: Vec<f32>,
+    pressures: Vec<f32>,
+}
+
+const DEEP: [f32; 3] = [0.05, 0.2, 0.6];
+const FOAM: [f32; 3] = [0.85, 0.95, 1.0];
+
+impl Sph {
+    pub fn new(smoothing_length: f32) -> Self {
+        Sph {
+            smoothing_length,
+            rest_density: WATER_DENSITY,
+            sound_speed: SOUND_SPEED,
+            exponent: TAIT_EXPONENT,
+            viscosity: ARTIFICIAL_VISCOSITY,
+            relativistic: false,
+            color_by_density: true,
+            grid: SpatialHash::new(),
+            boxes: Vec::new(),
+            pairs: Vec::new(),
+            lab_densities: Vec::new(),
+            densities: Vec::new(),
+            pressures: Vec::new(),
+        }
+    }
+
+    /// The cubic spline kernel `W(r, h)`, zero beyond `2h`.
+    pub fn kernel(&self, r: f32) -> f32 {
+        let h = self.smoothing_length;
+        let q = r / h;
+        let w = if q < 1.0 {
+            1.0 - 1.5 * q * q + 0.75 * q * q * q
+        } else if q < 2.0 {
+            0.25 * (2.0 - q).powi(3)
+        } else {
+            0.0
+        };
+        w / (PI * h * h * h)
+    }
+
+    /// `dW/dr`.
+    pub fn kernel_slope(&self, r: f32) -> f32 {
+        let h = self.smoothing_length;
+        let q = r / h;
+        let dw = if q < 1.0 {
+            -3.0 * q + 2.25 * q * q
+        } else if q < 2.0 {
+            -0.75 * (2.0 - q) * (2.0 - q)
+        } else {
+            0.0
+        };
+        dw / (PI * h * h * h * h)
+    }
+
+    /// Tait pressure at a rest frame `density`.
+    pub fn pressure(&self, density: f32) -> f32 {
+        let b = self.rest_density * self.sound_speed * self.sound_speed / self.exponent;
+        (b * ((density / self.rest_density).powf(self.exponent) - 1.0)).max(0.0)
+    }
+
+    /// Internal energy per unit mass stored by compressing the fluid to
+    /// `density`, the integral of `p / rho^2`.
+    pub fn internal_energy(&self, density: f32) -> f32 {
+        let (rho_0, n) = (self.rest_density, self.exponent);
+        if density <= rho_0 {
+            return 0.0;
+        }
+        let b = rho_0 * self.sound_speed * self.sound_speed / n;
+        b * (((density / rho_0).powf(n - 1.0) - 1.0) / (rho_0 * (n - 1.0)) + 1.0 / density
+            - 1.0 / rho_0)
+    }
+
+    /// Rest frame density of each particle, as of the last `accelerate`.
+    pub fn densities(&self) -> &[f32] {
+        &self.densities
+    }
+
+    /// Adds the pressure and viscous forces to each particle's
+    /// `acceleration`.
+    pub fn accelerate(&mut self, particles: &mut [Particle]) {
+        self.update_densities(particles);
+
+        let h = self.smoothing_length;
+        for &(i, j) in &self.pairs {
+            let (a, b) = (&particles[i], &particles[j]);
+            let d = a.spatial_position() - b.spatial_position();
+            let r = d.magnitude();
+            if r == 0.0 || r >= 2.0 * h {
+                continue;
+            }
+
+            let (n_a, n_b) = (self.lab_densities[i], self.lab_densities[j]);
+            let mut push = self.pressures[i] / (n_a * n_a) + self.pressures[j] / (n_b * n_b);
+
+            // Only between approaching particles
+            let closing = (a.three_velocity() - b.three_velocity()).dot(d);
+            if closing < 0.0 {
+                let mu = h * closing / (r * r + 0.01 * h * h);
+                push -= self.viscosity * self.sound_speed * mu / (0.5 * (n_a + n_b));
+            }
+
+            // Both ends are pushed apart along d by the same momentum
+            let f = d * (-push * self.kernel_slope(r) / r);
+            let (w_a, w_b) = (self.enthalpy(i), self.enthalpy(j));
+            let (m1, m2) = (a.mass, b.mass);
+            particles[i].acceleration += Vector4::new(0.0, f.x, f.y, f.z) * (m2 / w_a);
+            particles[j].acceleration -= Vector4::new(0.0, f.x, f.y, f.z) * (m1 / w_b);
+        }
+    }
+
+    /// Sets each particle's colour from its density, when `color_by_density`
+    /// is on.
+    pub fn paint(&self, particles: &mut [Particle]) {
+        if !self.color_by_density {
+            return;
+        }
+
+        for (p, &density) in particles.iter_mut().zip(&self.densities) {
+            let t = ((density / self.rest_density - 0.9) / 0.15).clamp(0.0, 1.0);
+            p.color = [0, 1, 2].map(|k| DEEP[k] + (FOAM[k] - DEEP[k]) * t);
+        }
+    }
+
+    /// Energy stored in the compressed fluid.
+    pub fn potential_energy(&self, particles: &[Particle]) -> f32 {
+        // Found afresh, as the densities may be out of date
+        let mut pairs = Vec::new();
+        find_neighbours(
+            particles,
+            self.smoothing_length,
+            &mut SpatialHash::new(),
+            &mut Vec::new(),
+            &mut pairs,
+        );
+
+        particles
+            .iter()
+            .zip(self.lab_densities_of(particles, &pairs))
+            .map(|(p, n)| p.mass * self.internal_energy(self.rest_frame(p, n)))
+            .sum()
+    }
+
+    fn update_densities(&mut self, particles: &[Particle]) {
+        find_neighbours(
+            particles,
+            self.smoothing_length,
+            &mut self.grid,
+            &mut self.boxes,
+            &mut self.pairs,
+        );
+
+        self.lab_densities = self.lab_densities_of(particles, &self.pairs);
+        self.densities = particles
+            .iter()
+            .zip(&self.lab_densities)
+            .map(|(p, &n)| self.rest_frame(p, n))
+            .collect();
+        self.pressures = self
+            .densities
+            .iter()
+            .map(|&density| self.pressure(density))
+            .collect();
+    }
+
+    /// Kernel sums over `pairs`, the density in the frame particles are seen
+    /// moving in.
+    fn lab_densities_of(&self, particles: &[Particle], pairs: &[(usize, usize)]) -> Vec<f32> {
+        // Every particle counts towards its own density
+        let self_weight = self.kernel(0.0);
+        let mut densities: Vec<f32> = particles.iter().map(|p| p.mass * self_weight).collect();
+        for &(i, j) in pairs {
+            let r = (particles[i].spatial_position() - particles[j].spatial_position()).magnitude();
+            let w = self.kernel(r);
+            densities[i] += particles[j].mass * w;
+            densities[j] += particles[i].mass * w;
+        }
+        densities
+    }
+
+    /// Density of `particle` in its own rest frame, from the lab frame one.
+    fn rest_frame(&self, particle: &Particle, lab_density: f32) -> f32 {
+        match self.relativistic {
+            true => lab_density / particle.gamma(),
+            false => lab_density,
+        }
+    }
+
+    /// Specific enthalpy of particle `i` over `c^2`, which is 1 unless
+    /// `relativistic` is on.
+    fn enthalpy(&self, i: usize) -> f32 {
+        if !self.relativistic {
+            return 1.0;
+        }
+        let density = self.densities[i];
+        1.0 + (self.internal_energy(density) + self.pressures[i] / density) / (C * C)
+    }
+}
+
+/// Every pair of particles that might be within `2h` of each other.
+fn find_neighbours(
+    particles: &[Particle],
+    h: f32,
+    grid: &mut SpatialHash,
+    boxes: &mut Vec<Aabb>,
+    out: &mut Vec<(usize, usize)>,
+) {
+    boxes.clear();
+    boxes.extend(
+        particles
+            .iter()
+            .map(|p| Aabb::around_sphere(p.spatial_position(), h)),
+    );
+    grid.find_pairs(boxes, out);
+}
+
+/// Positions of a block of `counts` particles `spacing` apart, centred on
+/// `center`, in x, then y, then z order.
+pub fn block(center: Vector3<f32>, counts: [usize; 3], spacing: f32) -> Vec<Vector3<f32>> {
+    let [nx, ny, nz] = counts;
+    let half = Vector3::new(nx as f32 - 1.0, ny as f32 - 1.0, nz as f32 - 1.0) * spacing / 2.0;
+
+    let mut points = Vec::with_capacity(nx * ny * nz);
+    for z in 0..nz {
+        for y in 0..ny {
+            for x in 0..nx {
+                let offset = Vector3::new(x as f32, y as f32, z as f32) * spacing;
+                points.push(center + offset - half);
+            }
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phys::{PhysicsWorld, integrator};
+
+    fn fluid(points: Vec<Vector3<f32>>, mass: f32, velocity: Vector3<f32>) -> Vec<Particle> {
+        let u = velocity;
+        points
+            .into_iter()
+            .map(|x| {
+                let mut p = Particle::new(
+                    Vector4::new(0.0, x.x, x.y, x.z),
+                    Vector4::new(C, 0.0, 0.0, 0.0),
+                    mass,
+                    0.1,
+                    [1.0, 1.0, 1.0],
+                    0.0,
+                );
+                p.set_three_velocity(u);
+                p
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kernel_is_normalised_and_matches_its_slope() {
+        let sph = Sph::new(0.7);
+
+        // Integrated over shells out to 2h
+        let steps = 2000;
+        let dr = 2.0 * sph.smoothing_length / steps as f32;
+        let total: f32 = (0..steps)
+            .map(|k| {
+                let r = (k as f32 + 0.5) * dr;
+                4.0 * PI * r * r * sph.kernel(r) * dr
+            })
+            .sum();
+        assert!((total - 1.0).abs() < 1e-4, "{}", total);
+
+        for r in [0.1, 0.5, 0.69, 0.71, 1.0, 1.3] {
+            let numeric = (sph.kernel(r + 1e-3) - sph.kernel(r - 1e-3)) / 2e-3;
+            assert!((numeric - sph.kernel_slope(r)).abs() < 1e-2, "r = {}", r);
+        }
+    }
+
+    #[test]
+    fn tait_pressure_stiffens_with_density() {
+        let sph = Sph::new(1.0);
+
+        assert_eq!(sph.pressure(sph.rest_density), 0.0);
+        assert_eq!(sph.pressure(0.5 * sph.rest_density), 0.0);
+        // dp/drho = c^2 at rest density
+        let dp = sph.pressure(1.001 * sph.rest_density) / (0.001 * sph.rest_density);
+        assert!((dp / (sph.sound_speed * sph.sound_speed) - 1.0).abs() < 1e-2);
+
+        // Internal energy is the integral of p / rho^2
+        let density = 1.05 * sph.rest_density;
+        let d = 0.1;
+        let slope =
+            (sph.internal_energy(density + d) - sph.internal_energy(density - d)) / (2.0 * d);
+        let expected = sph.pressure(density) / (density * density);
+        assert!((slope - expected).abs() < 1e-2 * expected);
+    }
+
+    #[test]
+    fn lattice_starts_at_rest_density() {
+        let spacing = 0.5;
+        let mut sph = Sph::new(1.3 * spacing);
+        let mass = sph.rest_density * spacing.powi(3);
+        let mut particles = fluid(
+            block(Vector3::new(0.0, 0.0, 0.0), [9, 9, 9], spacing),
+            mass,
+            Vector3::new(0.0, 0.0, 0.0),
+        );
+
+        sph.accelerate(&mut particles);
+
+        // The middle one is surrounded on every side
+        let density = sph.densities()[4 + 9 * (4 + 9 * 4)];
+        assert!(
+            (density / sph.rest_density - 1.0).abs() < 0.02,
+            "{}",
+            density
+        );
+        assert!(particles[4 + 9 * (4 + 9 * 4)].acceleration.magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn compressed_fluid_conserves_momentum_and_energy() {
+        let spacing = 0.5;
+        let mut world = PhysicsWorld::new();
+        world.set_integrator(integrator::by_name("leapfrog").unwrap());
+        let mut sph = Sph::new(1.3 * spacing);
+        sph.viscosity = 0.0;
+        let mass = sph.rest_density * spacing.powi(3);
+        world.sph = Some(sph);
+        // Squeezed to 90% of the spacing, with a push to one side
+        for p in fluid(
+            block(Vector3::new(0.0, 0.0, 0.0), [5, 5, 5], 0.9 * spacing),
+            mass,
+            Vector3::new(0.5, 0.0, 0.0),
+        ) {
+            world.add_particle(p);
+        }
+        let energy = world.energy();
+        let momentum = |world: &PhysicsWorld| -> Vector3<f32> {
+            world
+                .particles
+                .iter()
+                .map(|p| p.proper_velocity() * p.mass)
+                .sum()
+        };
+        let p0 = momentum(&world);
+
+        for _ in 0..500 {
+            world.update(0.0005);
+        }
+
+        assert!((momentum(&world) - p0).magnitude() < 1e-3 * p0.magnitude());
+        assert!(
+            ((world.energy() - energy) / energy).abs() < 0.02,
+            "{} -> {}",
+            energy,
+            world.energy()
+        );
+        // The pressure blew it apart
+        assert!(
+            world
+                .sph
+                .as_ref()
+                .unwrap()
+                .densities()
+                .iter()
+                .all(|&d| d < 1000.0)
+        );
+    }
+
+    #[test]
+    fn moving_fluid_is_lorentz_contracted() {
+        // The block flies along x at gamma 2, so it is half as long and twice
+        // as dense as it would be at rest
+        let spacing = 0.5;
+        let gamma: f32 = 2.0;
+        let speed = C * (1.0 - 1.0 / (gamma * gamma)).sqrt();
+        let mut sph = Sph::new(1.3 * spacing);
+        let mass = sph.rest_density * spacing.powi(3);
+        let points: Vec<Vector3<f32>> = block(Vector3::new(0.0, 0.0, 0.0), [17, 9, 9], spacing)
+            .into_iter()
+            .map(|x| Vector3::new(x.x / gamma, x.y, x.z))
+            .collect();
+        let mut particles = fluid(points, mass, Vector3::new(speed, 0.0, 0.0));
+        let middle = 8 + 17 * (4 + 9 * 4);
+
+        sph.accelerate(&mut particles);
+        let lab = sph.densities()[middle];
+        assert!(
+            (lab / (gamma * sph.rest_density) - 1.0).abs() < 0.02,
+            "{}",
+            lab
+        );
+
+        sph.relativistic = true;
+        sph.accelerate(&mut particles);
+        let rest = sph.densities()[middle];
+        assert!((rest / sph.rest_density - 1.0).abs() < 0.02, "{}", rest);
+    }
+}
@@ -0,0 +1,1672 @@
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::Path;
+
+use cgmath::{ElementWise, InnerSpace, Vector3, Vector4};
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::phys::constraint::Constraint;
+use crate::phys::coulomb::{self, Coulomb};
+use crate::phys::em::{EmField, OscillatingField, UniformField};
+use crate::phys::force::{Drag, ForceField, Radial, Uniform, Vortex};
+use crate::phys::gravity::{G, NBodyGravity};
+use crate::phys::material::{Combine, Material, MaterialId};
+use crate::phys::potential::{PairPotential, PairPotentials, SpeciesId};
+use crate::phys::softbody::Lattice;
+use crate::phys::sph::{self, Sph};
+use crate::phys::{C, CollisionMode, Particle, PhysicsWorld, Plane, PositionCorrection};
+use crate::phys::{broadphase, integrator};
+use crate::plane;
+use crate::threading::StepConfig;
+
+/// Initial conditions for a run, loaded from a TOML scene file.
+///
+/// ```toml
+/// [world]
+/// gravity = [0.0, -9.81, 0.0]
+/// integrator = "leapfrog" # euler, verlet, rk4, leapfrog or boris
+/// broadphase = "grid" # grid, sweep-x, sweep-y, sweep-z, tree or brute
+/// dt = 0.004
+/// max_substeps = 8
+/// slop = 0.01 # overlap left alone, as a fraction of the smaller radius
+/// baumgarte = 0.2 # fraction of the remaining overlap removed per step
+/// ccd = true # sub-step to impacts so fast particles can't tunnel
+/// neighbour_skin = 0.3 # slack in the pair potential neighbour list
+/// collisions = "bounce" # bounce, inelastic or merge
+/// constraint_iterations = 10 # solver passes over the rods and ropes per step
+///
+/// # Newtonian attraction between particles, off when the table is missing
+/// [n_body]
+/// g = 6.674e-11
+/// softening = 0.5 # Plummer length that keeps close encounters finite
+/// theta = 0.5 # Barnes-Hut opening angle, 0 to sum every pair directly
+///
+/// # Uniform electromagnetic field acting on charged particles
+/// [em_field]
+/// e = [0.0, 1000.0, 0.0] # V/m
+/// b = [0.0, 0.0, 1.0] # T
+/// frequency = 0.0 # Hz, both oscillate as cos(2 pi f t) when non-zero
+///
+/// # Electrostatics between charged particles, off when the table is missing
+/// [coulomb]
+/// k = 8.987552e9
+/// softening = 0.5
+/// retarded = false # full Liénard-Wiechert field of moving charges
+///
+/// # Makes every particle a parcel of SPH fluid, off when the table is missing
+/// [sph]
+/// smoothing_length = 0.65 # kernel width, about 1.3 times the spacing
+/// rest_density = 1000.0 # kg/m^3
+/// sound_speed = 20.0 # about ten times the fastest flow
+/// exponent = 7.0 # Tait equation stiffness
+/// viscosity = 0.05 # artificial viscosity
+/// relativistic = false # Lorentz contracted densities, for fast jets
+/// color_by_density = true # deep blue when thin to white when compressed
+///
+/// # External forces, as accelerations, summed with `world.gravity`
+/// [[force_field]]
+/// type = "radial" # uniform, radial, vortex or drag
+/// center = [0.0, 0.0, 0.0]
+/// strength = -50.0 # negative pulls inwards
+/// exponent = 1.0 # strength * r^exponent
+///
+/// [[force_field]]
+/// type = "vortex"
+/// center = [0.0, 0.0, 0.0]
+/// axis = [0.0, 1.0, 0.0]
+/// strength = 20.0
+///
+/// [[force_field]]
+/// type = "drag"
+/// linear = 0.1
+/// quadratic = 0.0
+///
+/// [[force_field]]
+/// type = "uniform"
+/// acceleration = [1.0, 0.0, 0.0]
+///
+/// # Short-range forces between particles of the given species
+/// [[pair_potential]]
+/// type = "lennard-jones" # lennard-jones, morse or soft-sphere
+/// species = [0, 1]
+/// epsilon = 1.0
+/// sigma = 1.0
+/// cutoff = 2.5
+///
+/// [[pair_potential]]
+/// type = "morse"
+/// species = [1, 1]
+/// depth = 1.0
+/// width = 2.0
+/// r0 = 1.2
+/// cutoff = 3.0
+///
+/// [[pair_potential]]
+/// type = "soft-sphere"
+/// species = [0, 0]
+/// epsilon = 1.0
+/// sigma = 1.0
+/// exponent = 12.0
+/// cutoff = 1.5
+///
+/// # Links between two `[[particle]]`s, by their order in the file. Linked
+/// # particles don't collide with each other.
+/// [[constraint]]
+/// type = "spring" # spring, distance or rope
+/// particles = [0, 1]
+/// length = 2.0 # rest length, the initial distance when left out
+/// stiffness = 100.0 # N/m
+/// damping = 0.5 # N s/m
+///
+/// [[constraint]]
+/// type = "rope" # slack until stretched to `length`
+/// particles = [1, 2]
+/// length = 5.0
+///
+/// # Referenced by name from particles, planes and clouds, which are
+/// # otherwise elastic and frictionless
+/// [[material]]
+/// name = "rubber"
+/// restitution = 0.8 # fraction of the normal velocity kept on a bounce
+/// static_friction = 0.9
+/// dynamic_friction = 0.7
+/// restitution_combine = "max" # average, min, multiply or max
+/// friction_combine = "average"
+///
+/// [[particle]]
+/// position = [0.0, 0.0, 5.0, 100.0] # ct, x, y, z
+/// velocity = [0.0, 0.0, -3000.0]
+/// mass = 1.0
+/// radius = 10.0
+/// color = [0.0, 0.6, 0.8]
+/// material = "rubber"
+/// charge = 0.0 # C
+/// species = 0 # picks the pair potentials it feels
+///
+/// [[plane]]
+/// center = [0.0, -20.0, 0.0]
+/// size = [400.0, 0.0, 400.0] # one zero extent for a flat plane, none for a box
+/// color = [0.3, 0.3, 0.3]
+///
+/// # Particles scattered uniformly through a box, from `world.seed`
+/// [[cloud]]
+/// count = 500
+/// center = [0.0, 0.0, 0.0]
+/// extent = [100.0, 100.0, 100.0] # half-size of the box
+/// speed = 1000.0 # largest velocity component
+/// mass = 1.0
+/// radius = 1.0
+///
+/// # A block of fluid particles on a grid, each as heavy as its share of the
+/// # rest density. Needs `[sph]`.
+/// [[fluid]]
+/// center = [0.0, 0.0, 0.0]
+/// count = [20, 20, 10]
+/// spacing = 0.5
+/// velocity = [0.0, 0.0, 0.0]
+/// radius = 0.25 # only for drawing and planes, defaults to half the spacing
+///
+/// # A grid of particles held together by springs and drawn as a mesh, a
+/// # sheet of cloth when one count is 1
+/// [[soft_body]]
+/// center = [0.0, 40.0, 0.0]
+/// count = [20, 1, 20] # particles along x, y and z
+/// spacing = 2.0
+/// velocity = [0.0, 0.0, 0.0]
+/// mass = 0.1 # of each particle
+/// radius = 0.8
+/// color = [0.8, 0.2, 0.3]
+/// stiffness = 200.0 # structural and shear springs, N/m
+/// damping = 0.1
+/// bend_stiffness = 0.0 # springs skipping a particle, against folding
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    #[serde(default)]
+    pub world: WorldDesc,
+    pub n_body: Option<Spanned<NBodyDesc>>,
+    pub em_field: Option<Spanned<EmFieldDesc>>,
+    pub coulomb: Option<Spanned<CoulombDesc>>,
+    pub sph: Option<Spanned<SphDesc>>,
+    #[serde(default, rename = "force_field")]
+    pub force_fields: Vec<Spanned<ForceFieldDesc>>,
+    #[serde(default, rename = "pair_potential")]
+    pub pair_potentials: Vec<Spanned<PairPotentialDesc>>,
+    #[serde(default, rename = "constraint")]
+    pub constraints: Vec<Spanned<ConstraintDesc>>,
+    #[serde(default, rename = "material")]
+    pub materials: Vec<Spanned<MaterialDesc>>,
+    #[serde(default, rename = "particle")]
+    pub particles: Vec<Spanned<ParticleDesc>>,
+    #[serde(default, rename = "plane")]
+    pub planes: Vec<Spanned<PlaneDesc>>,
+    #[serde(default, rename = "cloud")]
+    pub clouds: Vec<Spanned<CloudDesc>>,
+    #[serde(default, rename = "soft_body")]
+    pub soft_bodies: Vec<Spanned<SoftBodyDesc>>,
+    #[serde(default, rename = "fluid")]
+    pub fluids: Vec<Spanned<FluidDesc>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct WorldDesc {
+    pub gravity: Spanned<[f32; 3]>,
+    pub integrator: Spanned<String>,
+    pub broadphase: Spanned<String>,
+    pub dt: Spanned<f32>,
+    pub max_substeps: Spanned<u32>,
+    pub seed: u64,
+    pub slop: Spanned<f32>,
+    pub baumgarte: Spanned<f32>,
+    pub ccd: bool,
+    pub collisions: CollisionMode,
+    pub neighbour_skin: Spanned<f32>,
+    pub constraint_iterations: usize,
+}
+
+impl Default for WorldDesc {
+    fn default() -> Self {
+        let step = StepConfig::default();
+        let correction = PositionCorrection::default();
+        Self {
+            gravity: Spanned::new(0..0, [0.0; 3]),
+            integrator: Spanned::new(0..0, "euler".to_owned()),
+            broadphase: Spanned::new(0..0, "grid".to_owned()),
+            dt: Spanned::new(0..0, step.dt),
+            max_substeps: Spanned::new(0..0, step.max_substeps),
+            seed: 0,
+            slop: Spanned::new(0..0, correction.slop),
+            baumgarte: Spanned::new(0..0, correction.baumgarte),
+            ccd: false,
+            collisions: CollisionMode::Bounce,
+            neighbour_skin: Spanned::new(0..0, PairPotentials::new().skin),
+            constraint_iterations: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NBodyDesc {
+    #[serde(default = "gravitational_constant")]
+    pub g: f32,
+    #[serde(default)]
+    pub softening: f32,
+    #[serde(default)]
+    pub theta: f32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EmFieldDesc {
+    #[serde(default)]
+    pub e: [f32; 3],
+    #[serde(default)]
+    pub b: [f32; 3],
+    #[serde(default)]
+    pub frequency: f32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CoulombDesc {
+    #[serde(default = "coulomb_constant")]
+    pub k: f32,
+    #[serde(default)]
+    pub softening: f32,
+    #[serde(default)]
+    pub retarded: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SphDesc {
+    pub smoothing_length: f32,
+    #[serde(default = "water_density")]
+    pub rest_density: f32,
+    #[serde(default = "sound_speed")]
+    pub sound_speed: f32,
+    #[serde(default = "tait_exponent")]
+    pub exponent: f32,
+    #[serde(default = "artificial_viscosity")]
+    pub viscosity: f32,
+    #[serde(default)]
+    pub relativistic: bool,
+    #[serde(default = "yes")]
+    pub color_by_density: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ForceFieldDesc {
+    Uniform {
+        acceleration: [f32; 3],
+    },
+    Radial {
+        #[serde(default)]
+        center: [f32; 3],
+        strength: f32,
+        #[serde(default)]
+        exponent: f32,
+    },
+    Vortex {
+        #[serde(default)]
+        center: [f32; 3],
+        axis: [f32; 3],
+        strength: f32,
+    },
+    Drag {
+        #[serde(default)]
+        linear: f32,
+        #[serde(default)]
+        quadratic: f32,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum PairPotentialDesc {
+    LennardJones {
+        species: [SpeciesId; 2],
+        epsilon: f32,
+        sigma: f32,
+        cutoff: f32,
+    },
+    Morse {
+        species: [SpeciesId; 2],
+        depth: f32,
+        width: f32,
+        r0: f32,
+        cutoff: f32,
+    },
+    SoftSphere {
+        species: [SpeciesId; 2],
+        epsilon: f32,
+        sigma: f32,
+        exponent: f32,
+        cutoff: f32,
+    },
+}
+
+impl PairPotentialDesc {
+    /// The two species, the potential between them and its cutoff.
+    fn interaction(&self) -> ([SpeciesId; 2], PairPotential, f32) {
+        match *self {
+            PairPotentialDesc::LennardJones {
+                species,
+                epsilon,
+                sigma,
+                cutoff,
+            } => (
+                species,
+                PairPotential::LennardJones { epsilon, sigma },
+                cutoff,
+            ),
+            PairPotentialDesc::Morse {
+                species,
+                depth,
+                width,
+                r0,
+                cutoff,
+            } => (species, PairPotential::Morse { depth, width, r0 }, cutoff),
+            PairPotentialDesc::SoftSphere {
+                species,
+                epsilon,
+                sigma,
+                exponent,
+                cutoff,
+            } => (
+                species,
+                PairPotential::SoftSphere {
+                    epsilon,
+                    sigma,
+                    exponent,
+                },
+                cutoff,
+            ),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ConstraintDesc {
+    Spring {
+        particles: [usize; 2],
+        length: Option<f32>,
+        stiffness: f32,
+        #[serde(default)]
+        damping: f32,
+    },
+    Distance {
+        particles: [usize; 2],
+        length: Option<f32>,
+    },
+    Rope {
+        particles: [usize; 2],
+        length: Option<f32>,
+    },
+}
+
+impl ConstraintDesc {
+    fn particles(&self) -> [usize; 2] {
+        match *self {
+            ConstraintDesc::Spring { particles, .. }
+            | ConstraintDesc::Distance { particles, .. }
+            | ConstraintDesc::Rope { particles, .. } => particles,
+        }
+    }
+
+    fn length(&self) -> Option<f32> {
+        match *self {
+            ConstraintDesc::Spring { length, .. }
+            | ConstraintDesc::Distance { length, .. }
+            | ConstraintDesc::Rope { length, .. } => length,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDesc {
+    pub name: String,
+    #[serde(default = "elastic")]
+    pub restitution: f32,
+    #[serde(default)]
+    pub static_friction: f32,
+    #[serde(default)]
+    pub dynamic_friction: f32,
+    #[serde(default)]
+    pub restitution_combine: Combine,
+    #[serde(default)]
+    pub friction_combine: Combine,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ParticleDesc {
+    pub position: [f32; 4],
+    #[serde(default)]
+    pub velocity: [f32; 3],
+    pub mass: f32,
+    pub radius: f32,
+    #[serde(default = "white")]
+    pub color: [f32; 3],
+    pub material: Option<Spanned<String>>,
+    #[serde(default)]
+    pub charge: f32,
+    #[serde(default)]
+    pub species: SpeciesId,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PlaneDesc {
+    pub center: [f32; 3],
+    pub size: [f32; 3],
+    #[serde(default = "white")]
+    pub color: [f32; 3],
+    pub material: Option<Spanned<String>>,
+    /// Deprecated in favour of `material`, and stands for a material that is
+    /// only this restitution.
+    pub restitution: Option<f32>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CloudDesc {
+    pub count: usize,
+    pub center: [f32; 3],
+    pub extent: [f32; 3],
+    #[serde(default)]
+    pub speed: f32,
+    pub mass: f32,
+    pub radius: f32,
+    #[serde(default = "white")]
+    pub color: [f32; 3],
+    pub material: Option<Spanned<String>>,
+    #[serde(default)]
+    pub charge: f32,
+    #[serde(default)]
+    pub species: SpeciesId,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SoftBodyDesc {
+    pub center: [f32; 3],
+    pub count: [usize; 3],
+    pub spacing: f32,
+    #[serde(default)]
+    pub velocity: [f32; 3],
+    pub mass: f32,
+    pub radius: f32,
+    #[serde(default = "white")]
+    pub color: [f32; 3],
+    pub material: Option<Spanned<String>>,
+    pub stiffness: f32,
+    #[serde(default)]
+    pub damping: f32,
+    #[serde(default)]
+    pub bend_stiffness: f32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FluidDesc {
+    pub center: [f32; 3],
+    pub count: [usize; 3],
+    pub spacing: f32,
+    #[serde(default)]
+    pub velocity: [f32; 3],
+    pub radius: Option<f32>,
+    #[serde(default = "white")]
+    pub color: [f32; 3],
+    pub material: Option<Spanned<String>>,
+}
+
+fn white() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn elastic() -> f32 {
+    1.0
+}
+
+fn gravitational_constant() -> f32 {
+    G
+}
+
+fn coulomb_constant() -> f32 {
+    coulomb::K
+}
+
+fn water_density() -> f32 {
+    sph::WATER_DENSITY
+}
+
+fn sound_speed() -> f32 {
+    sph::SOUND_SPEED
+}
+
+fn tait_exponent() -> f32 {
+    sph::TAIT_EXPONENT
+}
+
+fn artificial_viscosity() -> f32 {
+    sph::ARTIFICIAL_VISCOSITY
+}
+
+/// Finite and above zero, which rules out NaN too.
+fn positive(x: f32) -> bool {
+    x.is_finite() && x > 0.0
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Self, SceneError> {
+        let src = std::fs::read_to_string(path).map_err(SceneError::Io)?;
+        Self::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<Self, SceneError> {
+        let scene: Scene = toml::from_str(src)
+            .map_err(|e| parse_error(src, e.span().unwrap_or(0..0), e.message().to_owned()))?;
+
+        scene
+            .validate()
+            .map_err(|(span, message)| parse_error(src, span, message))?;
+
+        Ok(scene)
+    }
+
+    fn validate(&self) -> Result<(), (Range<usize>, String)> {
+        let world = &self.world;
+
+        if integrator::by_name(world.integrator.get_ref()).is_none() {
+            return Err((
+                world.integrator.span(),
+                format!(
+                    "unknown integrator `{}`, expected euler, verlet, rk4, leapfrog or boris",
+                    world.integrator.get_ref()
+                ),
+            ));
+        }
+
+        if broadphase::by_name(world.broadphase.get_ref()).is_none() {
+            return Err((
+                world.broadphase.span(),
+                format!(
+                    "unknown broadphase `{}`, expected grid, sweep-x, sweep-y, sweep-z, tree or brute",
+                    world.broadphase.get_ref()
+                ),
+            ));
+        }
+
+        if !positive(*world.dt.get_ref()) {
+            return Err((world.dt.span(), "dt must be positive and finite".to_owned()));
+        }
+        if *world.max_substeps.get_ref() == 0 {
+            return Err((
+                world.max_substeps.span(),
+                "max_substeps must be at least 1".to_owned(),
+            ));
+        }
+        if world.gravity.get_ref().iter().any(|g| !g.is_finite()) {
+            return Err((world.gravity.span(), "gravity must be finite".to_owned()));
+        }
+
+        let slop = *world.slop.get_ref();
+        if slop.is_nan() || slop < 0.0 {
+            return Err((world.slop.span(), "slop must not be negative".to_owned()));
+        }
+        if !(0.0..=1.0).contains(world.baumgarte.get_ref()) {
+            return Err((
+                world.baumgarte.span(),
+                "baumgarte must be in [0, 1]".to_owned(),
+            ));
+        }
+
+        for p in &self.particles {
+            let desc = p.get_ref();
+            if ![desc.mass, desc.radius].into_iter().all(positive) {
+                return Err((p.span(), "mass and radius must be positive".to_owned()));
+            }
+            if desc
+                .position
+                .iter()
+                .chain(&desc.velocity)
+                .any(|x| !x.is_finite())
+            {
+                return Err((p.span(), "position and velocity must be finite".to_owned()));
+            }
+            if Vector3::from(desc.velocity).magnitude() >= C {
+                return Err((p.span(), "particle speed must be below C".to_owned()));
+            }
+            if !desc.charge.is_finite() {
+                return Err((p.span(), "charge must be finite".to_owned()));
+            }
+        }
+
+        for p in &self.planes {
+            let desc = p.get_ref();
+            if desc.size.iter().any(|&l| l < 0.0)
+                || desc.size.iter().filter(|&&l| l == 0.0).count() > 1
+            {
+                return Err((
+                    p.span(),
+                    "plane size needs at most one zero extent and no negative ones".to_owned(),
+                ));
+            }
+            if let Some(restitution) = desc.restitution {
+                if desc.material.is_some() {
+                    return Err((
+                        p.span(),
+                        "set restitution in the plane's [[material]] instead".to_owned(),
+                    ));
+                }
+                if !(0.0..=1.0).contains(&restitution) {
+                    return Err((p.span(), "restitution must be in [0, 1]".to_owned()));
+                }
+            }
+        }
+
+        if let Some(n_body) = &self.n_body {
+            let desc = n_body.get_ref();
+            if !desc.g.is_finite() || desc.softening.is_nan() || desc.softening < 0.0 {
+                return Err((
+                    n_body.span(),
+                    "g must be finite and softening not negative".to_owned(),
+                ));
+            }
+            if desc.theta.is_nan() || desc.theta < 0.0 {
+                return Err((n_body.span(), "theta must not be negative".to_owned()));
+            }
+        }
+
+        if let Some(field) = &self.em_field {
+            let desc = field.get_ref();
+            if desc
+                .e
+                .iter()
+                .chain(&desc.b)
+                .chain([&desc.frequency])
+                .any(|x| !x.is_finite())
+            {
+                return Err((field.span(), "field values must be finite".to_owned()));
+            }
+        }
+
+        if let Some(c) = &self.coulomb {
+            let desc = c.get_ref();
+            if !desc.k.is_finite() || desc.softening.is_nan() || desc.softening < 0.0 {
+                return Err((
+                    c.span(),
+                    "k must be finite and softening not negative".to_owned(),
+                ));
+            }
+        }
+
+        for f in &self.force_fields {
+            let values: Vec<f32> = match *f.get_ref() {
+                ForceFieldDesc::Uniform { acceleration } => acceleration.to_vec(),
+                ForceFieldDesc::Radial {
+                    center,
+                    strength,
+                    exponent,
+                } => center
+                    .iter()
+                    .chain([&strength, &exponent])
+                    .copied()
+                    .collect(),
+                ForceFieldDesc::Vortex {
+                    center,
+                    axis,
+                    strength,
+                } => center
+                    .iter()
+                    .chain(&axis)
+                    .chain([&strength])
+                    .copied()
+                    .collect(),
+                ForceFieldDesc::Drag { linear, quadratic } => vec![linear, quadratic],
+            };
+            if values.iter().any(|x| !x.is_finite()) {
+                return Err((f.span(), "field values must be finite".to_owned()));
+            }
+            if let ForceFieldDesc::Vortex { axis, .. } = f.get_ref()
+                && Vector3::from(*axis).magnitude2() == 0.0
+            {
+                return Err((f.span(), "vortex axis must not be zero".to_owned()));
+            }
+        }
+
+        let skin = *world.neighbour_skin.get_ref();
+        if skin.is_nan() || skin < 0.0 {
+            return Err((
+                world.neighbour_skin.span(),
+                "neighbour_skin must not be negative".to_owned(),
+            ));
+        }
+        for p in &self.pair_potentials {
+            let (_, potential, cutoff) = p.get_ref().interaction();
+            if cutoff.is_nan() || cutoff <= 0.0 {
+                return Err((p.span(), "cutoff must be positive".to_owned()));
+            }
+            if !potential.energy(cutoff).is_finite() {
+                return Err((
+                    p.span(),
+                    "potential must be finite at the cutoff".to_owned(),
+                ));
+            }
+        }
+
+        for c in &self.constraints {
+            let desc = c.get_ref();
+            let [a, b] = desc.particles();
+            if a == b || a >= self.particles.len() || b >= self.particles.len() {
+                return Err((
+                    c.span(),
+                    format!(
+                        "constraint needs two different particles out of {}",
+                        self.particles.len()
+                    ),
+                ));
+            }
+            if desc.length().is_some_and(|l| l.is_nan() || l < 0.0) {
+                return Err((c.span(), "length must not be negative".to_owned()));
+            }
+            if let ConstraintDesc::Spring {
+                stiffness, damping, ..
+            } = *desc
+                && (stiffness.is_nan() || stiffness <= 0.0 || damping.is_nan() || damping < 0.0)
+            {
+                return Err((
+                    c.span(),
+                    "stiffness must be positive and damping not negative".to_owned(),
+                ));
+            }
+        }
+
+        for (k, m) in self.materials.iter().enumerate() {
+            let desc = m.get_ref();
+            if self.materials[..k]
+                .iter()
+                .any(|other| other.get_ref().name == desc.name)
+            {
+                return Err((
+                    m.span(),
+                    format!("material `{}` is defined twice", desc.name),
+                ));
+            }
+            if !(0.0..=1.0).contains(&desc.restitution) {
+                return Err((m.span(), "restitution must be in [0, 1]".to_owned()));
+            }
+            if desc.static_friction.is_nan()
+                || desc.static_friction < 0.0
+                || desc.dynamic_friction.is_nan()
+                || desc.dynamic_friction < 0.0
+            {
+                return Err((m.span(), "friction must not be negative".to_owned()));
+            }
+        }
+
+        let references = self
+            .particles
+            .iter()
+            .map(|p| &p.get_ref().material)
+            .chain(self.planes.iter().map(|p| &p.get_ref().material))
+            .chain(self.clouds.iter().map(|c| &c.get_ref().material))
+            .chain(self.soft_bodies.iter().map(|b| &b.get_ref().material))
+            .chain(self.fluids.iter().map(|f| &f.get_ref().material));
+        for name in references.flatten() {
+            if self.material_id(Some(name)).is_none() {
+                return Err((
+                    name.span(),
+                    format!("unknown material `{}`", name.get_ref()),
+                ));
+            }
+        }
+
+        for c in &self.clouds {
+            let desc = c.get_ref();
+            if ![desc.mass, desc.radius].into_iter().all(positive) {
+                return Err((c.span(), "mass and radius must be positive".to_owned()));
+            }
+            if desc
+                .center
+                .iter()
+                .chain(&desc.extent)
+                .any(|x| !x.is_finite())
+            {
+                return Err((c.span(), "center and extent must be finite".to_owned()));
+            }
+            if !(desc.speed >= 0.0 && desc.speed * 3f32.sqrt() < C) {
+                return Err((
+                    c.span(),
+                    "cloud speed must be in [0, C / sqrt(3))".to_owned(),
+                ));
+            }
+            if !desc.charge.is_finite() {
+                return Err((c.span(), "charge must be finite".to_owned()));
+            }
+        }
+
+        for b in &self.soft_bodies {
+            let desc = b.get_ref();
+            if desc.count.contains(&0) {
+                return Err((
+                    b.span(),
+                    "count must be at least 1 along each axis".to_owned(),
+                ));
+            }
+            if ![desc.mass, desc.radius, desc.spacing]
+                .into_iter()
+                .all(positive)
+            {
+                return Err((
+                    b.span(),
+                    "mass, radius and spacing must be positive".to_owned(),
+                ));
+            }
+            if desc
+                .center
+                .iter()
+                .chain(&desc.velocity)
+                .any(|x| !x.is_finite())
+            {
+                return Err((b.span(), "center and velocity must be finite".to_owned()));
+            }
+            if desc.stiffness.is_nan()
+                || desc.stiffness <= 0.0
+                || desc.damping.is_nan()
+                || desc.damping < 0.0
+                || desc.bend_stiffness.is_nan()
+                || desc.bend_stiffness < 0.0
+            {
+                return Err((
+                    b.span(),
+                    "stiffness must be positive, damping and bend_stiffness not negative"
+                        .to_owned(),
+                ));
+            }
+            if Vector3::from(desc.velocity).magnitude() >= C {
+                return Err((b.span(), "soft body speed must be below C".to_owned()));
+            }
+        }
+
+        if let Some(sph) = &self.sph {
+            let desc = sph.get_ref();
+            if ![desc.smoothing_length, desc.rest_density, desc.sound_speed]
+                .into_iter()
+                .all(positive)
+            {
+                return Err((
+                    sph.span(),
+                    "smoothing_length, rest_density and sound_speed must be positive".to_owned(),
+                ));
+            }
+            if !(desc.exponent.is_finite() && desc.exponent >= 1.0) {
+                return Err((
+                    sph.span(),
+                    "exponent must be finite and at least 1".to_owned(),
+                ));
+            }
+            if !(desc.viscosity.is_finite() && desc.viscosity >= 0.0) {
+                return Err((
+                    sph.span(),
+                    "viscosity must be finite and not negative".to_owned(),
+                ));
+            }
+        }
+        for f in &self.fluids {
+            let desc = f.get_ref();
+            if self.sph.is_none() {
+                return Err((f.span(), "fluid needs an [sph] table".to_owned()));
+            }
+            if desc.count.contains(&0) {
+                return Err((
+                    f.span(),
+                    "count must be at least 1 along each axis".to_owned(),
+                ));
+            }
+            if !positive(desc.spacing) || desc.radius.is_some_and(|r| !positive(r)) {
+                return Err((f.span(), "spacing and radius must be positive".to_owned()));
+            }
+            if desc
+                .center
+                .iter()
+                .chain(&desc.velocity)
+                .any(|x| !x.is_finite())
+            {
+                return Err((f.span(), "center and velocity must be finite".to_owned()));
+            }
+            if Vector3::from(desc.velocity).magnitude() >= C {
+                return Err((f.span(), "fluid speed must be below C".to_owned()));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn step_config(&self) -> StepConfig {
+        StepConfig {
+            dt: *self.world.dt.get_ref(),
+            max_substeps: *self.world.max_substeps.get_ref(),
+        }
+    }
+
+    pub fn build(&self) -> PhysicsWorld {
+        let mut world = PhysicsWorld::new();
+
+        let [gx, gy, gz] = *self.world.gravity.get_ref();
+        world.gravity = Vector4::new(0.0, gx, gy, gz);
+        world.correction = PositionCorrection {
+            slop: *self.world.slop.get_ref(),
+            baumgarte: *self.world.baumgarte.get_ref(),
+        };
+        world.ccd = self.world.ccd;
+        world.collision_mode = self.world.collisions;
+        world.n_body = self.n_body.as_ref().map(|n_body| {
+            let desc = n_body.get_ref();
+            NBodyGravity {
+                g: desc.g,
+                softening: desc.softening,
+                theta: desc.theta,
+            }
+        });
+        for f in &self.force_fields {
+            let field: Box<dyn ForceField> = match *f.get_ref() {
+                ForceFieldDesc::Uniform { acceleration } => Box::new(Uniform {
+                    acceleration: acceleration.into(),
+                }),
+                ForceFieldDesc::Radial {
+                    center,
+                    strength,
+                    exponent,
+                } => Box::new(Radial {
+                    center: center.into(),
+                    strength,
+                    exponent,
+                }),
+                ForceFieldDesc::Vortex {
+                    center,
+                    axis,
+                    strength,
+                } => Box::new(Vortex {
+                    center: center.into(),
+                    axis: axis.into(),
+                    strength,
+                }),
+                ForceFieldDesc::Drag { linear, quadratic } => Box::new(Drag { linear, quadratic }),
+            };
+            world.force_fields.push(field);
+        }
+        if !self.pair_potentials.is_empty() {
+            let mut potentials = PairPotentials::new();
+            potentials.skin = *self.world.neighbour_skin.get_ref();
+            for p in &self.pair_potentials {
+                let ([a, b], potential, cutoff) = p.get_ref().interaction();
+                potentials.set(a, b, potential, cutoff);
+            }
+            world.pair_potentials = Some(potentials);
+        }
+        world.coulomb = self.coulomb.as_ref().map(|c| {
+            let desc = c.get_ref();
+            Coulomb {
+                k: desc.k,
+                softening: desc.softening,
+                retarded: desc.retarded,
+            }
+        });
+        world.sph = self.sph.as_ref().map(|sph| {
+            let desc = sph.get_ref();
+            let mut sph = Sph::new(desc.smoothing_length);
+            sph.rest_density = desc.rest_density;
+            sph.sound_speed = desc.sound_speed;
+            sph.exponent = desc.exponent;
+            sph.viscosity = desc.viscosity;
+            sph.relativistic = desc.relativistic;
+            sph.color_by_density = desc.color_by_density;
+            sph
+        });
+        world.em_field = self.em_field.as_ref().map(|field| {
+            let desc = field.get_ref();
+            let (e, b) = (Vector3::from(desc.e), Vector3::from(desc.b));
+            if desc.frequency == 0.0 {
+                Box::new(UniformField { e, b }) as Box<dyn EmField>
+            } else {
+                Box::new(OscillatingField {
+                    e,
+                    b,
+                    angular_frequency: std::f32::consts::TAU * desc.frequency,
+                })
+            }
+        });
+
+        if let Some(integrator) = integrator::by_name(self.world.integrator.get_ref()) {
+            world.set_integrator(integrator);
+        }
+        if let Some(broadphase) = broadphase::by_name(self.world.broadphase.get_ref()) {
+            world.set_broadphase(broadphase);
+        }
+
+        world.materials.extend(self.materials.iter().map(|m| {
+            let m = m.get_ref();
+            Material {
+                restitution: m.restitution,
+                static_friction: m.static_friction,
+                dynamic_friction: m.dynamic_friction,
+                restitution_combine: m.restitution_combine,
+                friction_combine: m.friction_combine,
+            }
+        }));
+        world.materials.extend(self.plane_restitutions());
+
+        for p in &self.particles {
+            let p = p.get_ref();
+            let [vx, vy, vz] = p.velocity;
+            let mut particle = Particle::new(
+                Vector4::from(p.position),
+                Vector4::new(C, vx, vy, vz),
+                p.mass,
+                p.radius,
+                p.color,
+                0.0,
+            );
+            particle.material = self.material_id(p.material.as_ref()).unwrap_or(0);
+            particle.charge = p.charge;
+            particle.species = p.species;
+            world.add_particle(particle);
+        }
+
+        let mut rng = SplitMix64(self.world.seed);
+        for c in &self.clouds {
+            let c = c.get_ref();
+            let center = Vector3::from(c.center);
+            let extent = Vector3::from(c.extent);
+            let material = self.material_id(c.material.as_ref()).unwrap_or(0);
+
+            for _ in 0..c.count {
+                let x = center + extent.mul_element_wise(rng.next_vector());
+                let v = rng.next_vector() * c.speed;
+                let mut particle = Particle::new(
+                    Vector4::new(0.0, x.x, x.y, x.z),
+                    Vector4::new(C, v.x, v.y, v.z),
+                    c.mass,
+                    c.radius,
+                    c.color,
+                    0.0,
+                );
+                particle.material = material;
+                particle.charge = c.charge;
+                particle.species = c.species;
+                world.add_particle(particle);
+            }
+        }
+
+        let rest_density = world.sph.as_ref().map_or(0.0, |sph| sph.rest_density);
+        for f in &self.fluids {
+            let f = f.get_ref();
+            let [vx, vy, vz] = f.velocity;
+            let material = self.material_id(f.material.as_ref()).unwrap_or(0);
+
+            for x in sph::block(f.center.into(), f.count, f.spacing) {
+                let mut particle = Particle::new(
+                    Vector4::new(0.0, x.x, x.y, x.z),
+                    Vector4::new(C, vx, vy, vz),
+                    rest_density * f.spacing.powi(3),
+                    f.radius.unwrap_or(0.5 * f.spacing),
+                    f.color,
+                    0.0,
+                );
+                particle.material = material;
+                world.add_particle(particle);
+            }
+        }
+
+        for b in &self.soft_bodies {
+            let b = b.get_ref();
+            Lattice {
+                center: b.center.into(),
+                counts: b.count,
+                spacing: b.spacing,
+                velocity: b.velocity.into(),
+                mass: b.mass,
+                radius: b.radius,
+                color: b.color,
+                material: self.material_id(b.material.as_ref()).unwrap_or(0),
+                stiffness: b.stiffness,
+                damping: b.damping,
+                bend_stiffness: b.bend_stiffness,
+            }
+            .build(&mut world);
+        }
+
+        world.constraint_iterations = self.world.constraint_iterations;
+        for c in &self.constraints {
+            let desc = c.get_ref();
+            let [a, b] = desc.particles();
+            let length = desc.length().unwrap_or_else(|| {
+                let d =
+                    world.particles[a].spatial_position() - world.particles[b].spatial_position();
+                d.magnitude()
+            });
+            world.constraints.push(match *desc {
+                ConstraintDesc::Spring {
+                    stiffness, damping, ..
+                } => Constraint::spring(a, b, length, stiffness, damping),
+                ConstraintDesc::Distance { .. } => Constraint::distance(a, b, length),
+                ConstraintDesc::Rope { .. } => Constraint::rope(a, b, length),
+            });
+        }
+
+        world.planes = self.planes();
+
+        world
+    }
+
+    /// The scene's planes and boxes, which never move.
+    pub fn planes(&self) -> Vec<Plane> {
+        let mut anonymous = 1 + self.materials.len();
+        self.planes
+            .iter()
+            .map(|p| {
+                let p = p.get_ref();
+                let ([x, y, z], [xl, yl, zl]) = (p.center, p.size);
+                let material = match p.restitution {
+                    Some(_) => {
+                        anonymous += 1;
+                        anonymous - 1
+                    }
+                    None => self.material_id(p.material.as_ref()).unwrap_or(0),
+                };
+                plane![x, y, z; xl, yl, zl; p.color; material]
+            })
+            .collect()
+    }
+
+    /// Materials standing in for the deprecated `restitution` of planes, which
+    /// come after the named ones.
+    fn plane_restitutions(&self) -> impl Iterator<Item = Material> + '_ {
+        self.planes
+            .iter()
+            .filter_map(|p| p.get_ref().restitution)
+            .map(|restitution| Material {
+                restitution,
+                ..Material::default()
+            })
+    }
+
+    /// Index a material name refers to in the built world's `materials`, with
+    /// no name meaning the default material.
+    fn material_id(&self, name: Option<&Spanned<String>>) -> Option<MaterialId> {
+        let Some(name) = name else {
+            return Some(0);
+        };
+
+        self.materials
+            .iter()
+            .position(|m| m.get_ref().name == *name.get_ref())
+            .map(|k| k + 1)
+    }
+}
+
+/// Small deterministic generator so a seed reproduces the same scene everywhere.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [-1, 1).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+
+    fn next_vector(&mut self) -> Vector3<f32> {
+        Vector3::new(self.next_f32(), self.next_f32(), self.next_f32())
+    }
+}
+
+/// Turns a byte span in `src` into a 1-based line and column.
+fn parse_error(src: &str, span: Range<usize>, message: String) -> SceneError {
+    let before = &src[..span.start.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+    SceneError::Parse {
+        line,
+        column,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_particles_and_world() {
+        let scene = Scene::parse(
+            r#"
+[world]
+integrator = "rk4"
+broadphase = "tree"
+collisions = "merge"
+dt = 0.01
+
+[n_body]
+softening = 0.5
+theta = 0.7
+
+[[particle]]
+position = [0.0, 1.0, 2.0, 3.0]
+velocity = [10.0, 0.0, 0.0]
+mass = 2.0
+radius = 0.5
+"#,
+        )
+        .unwrap();
+
+        let world = scene.build();
+        assert_eq!(world.integrator().name(), "rk4");
+        assert_eq!(world.broadphase().name(), "tree");
+        assert_eq!(world.collision_mode, CollisionMode::Merge);
+        assert_eq!(
+            world.n_body,
+            Some(NBodyGravity {
+                g: G,
+                softening: 0.5,
+                theta: 0.7,
+            })
+        );
+        assert_eq!(world.particles.len(), 1);
+        assert_eq!(world.particles[0].three_velocity().x, 10.0);
+        assert_eq!(scene.step_config().dt, 0.01);
+    }
+
+    #[test]
+    fn charges_must_be_finite() {
+        for src in [
+            "[[particle]]\nposition = [0.0, 0.0, 0.0, 0.0]\nmass = 1.0\nradius = 1.0\ncharge = inf\n",
+            "[[cloud]]\ncount = 2\ncenter = [0.0, 0.0, 0.0]\nextent = [1.0, 1.0, 1.0]\nmass = 1.0\nradius = 0.1\ncharge = nan\n",
+        ] {
+            let err = Scene::parse(src).unwrap_err();
+            assert!(err.to_string().contains("charge"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn force_fields_are_built_in_order() {
+        let scene = Scene::parse(
+            r#"
+[[force_field]]
+type = "drag"
+linear = 0.5
+
+[[force_field]]
+type = "uniform"
+acceleration = [0.0, -1.0, 0.0]
+
+[[particle]]
+position = [0.0, 0.0, 0.0, 0.0]
+velocity = [2.0, 0.0, 0.0]
+mass = 1.0
+radius = 1.0
+"#,
+        )
+        .unwrap();
+
+        let world = scene.build();
+        assert_eq!(world.force_fields.len(), 2);
+        let a: Vector3<f32> = world
+            .force_fields
+            .iter()
+            .map(|f| f.acceleration(&world.particles[0], 0.0))
+            .sum();
+        assert_eq!(a, Vector3::new(-1.0, -1.0, 0.0));
+
+        let err = Scene::parse("[[force_field]]\ntype = \"whirlpool\"\n").unwrap_err();
+        assert!(matches!(err, SceneError::Parse { line: 2, .. }), "{}", err);
+        let err =
+            Scene::parse("[[force_field]]\ntype = \"radial\"\nstrength = 1.0\nexponent = nan\n")
+                .unwrap_err();
+        assert!(err.to_string().contains("finite"), "{}", err);
+    }
+
+    #[test]
+    fn pair_potentials_are_set_by_species() {
+        let scene = Scene::parse(
+            r#"
+[[pair_potential]]
+type = "lennard-jones"
+species = [0, 1]
+epsilon = 2.0
+sigma = 1.0
+cutoff = 2.5
+
+[[particle]]
+position = [0.0, 0.0, 0.0, 0.0]
+mass = 1.0
+radius = 0.1
+species = 1
+"#,
+        )
+        .unwrap();
+
+        let world = scene.build();
+        let Some(potentials) = &world.pair_potentials else {
+            panic!("no pair potentials");
+        };
+        assert_eq!(
+            potentials.get(1, 0).map(|i| i.potential),
+            Some(PairPotential::LennardJones {
+                epsilon: 2.0,
+                sigma: 1.0
+            })
+        );
+        assert!(potentials.get(0, 0).is_none());
+        assert_eq!(world.particles[0].species, 1);
+    }
+
+    #[test]
+    fn materials_are_looked_up_by_name() {
+        let scene = Scene::parse(
+            r#"
+[[material]]
+name = "rubber"
+restitution = 0.8
+static_friction = 0.9
+friction_combine = "max"
+
+[[particle]]
+position = [0.0, 0.0, 0.0, 0.0]
+mass = 1.0
+radius = 1.0
+material = "rubber"
+
+[[plane]]
+center = [0.0, -2.0, 0.0]
+size = [10.0, 0.0, 10.0]
+"#,
+        )
+        .unwrap();
+
+        let world = scene.build();
+        let rubber = world.materials[world.particles[0].material];
+        assert_eq!(rubber.restitution, 0.8);
+        assert_eq!(rubber.friction_combine, Combine::Max);
+        assert_eq!(world.planes[0].material, 0);
+
+        let err = Scene::parse(
+            "[[particle]]\nposition = [0.0, 0.0, 0.0, 0.0]\nmass = 1.0\nradius = 1.0\nmaterial = \"ice\"\n",
+        )
+        .unwrap_err();
+        match err {
+            SceneError::Parse { line, message, .. } => {
+                assert_eq!(line, 5);
+                assert!(message.contains("ice"), "{}", message);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn plane_restitution_becomes_its_own_material() {
+        let scene = Scene::parse(
+            r#"
+[[material]]
+name = "rubber"
+restitution = 0.8
+
+[[plane]]
+center = [0.0, -2.0, 0.0]
+size = [10.0, 0.0, 10.0]
+restitution = 0.5
+
+[[plane]]
+center = [0.0, 2.0, 0.0]
+size = [10.0, 0.0, 10.0]
+material = "rubber"
+"#,
+        )
+        .unwrap();
+
+        let world = scene.build();
+        assert_eq!(world.materials.len(), 3);
+        assert_eq!(world.materials[world.planes[0].material].restitution, 0.5);
+        assert_eq!(world.planes[1].material, 1);
+
+        let err = Scene::parse(
+            "[[plane]]\ncenter = [0.0, 0.0, 0.0]\nsize = [1.0, 0.0, 1.0]\nmaterial = \"rubber\"\nrestitution = 0.5\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("[[material]]"), "{}", err);
+    }
+
+    #[test]
+    fn constraints_link_particles_in_order() {
+        let scene = Scene::parse(
+            r#"
+[[constraint]]
+type = "spring"
+particles = [0, 1]
+stiffness = 10.0
+
+[[constraint]]
+type = "rope"
+particles = [2, 1]
+length = 4.0
+
+[[particle]]
+position = [0.0, 0.0, 0.0, 0.0]
+mass = 1.0
+radius = 0.1
+
+[[particle]]
+position = [0.0, 3.0, 0.0, 0.0]
+mass = 1.0
+radius = 0.1
+
+[[particle]]
+position = [0.0, 3.0, 1.0, 0.0]
+mass = 1.0
+radius = 0.1
+"#,
+        )
+        .unwrap();
+
+        let world = scene.build();
+        assert_eq!(
+            world.constraints[0],
+            Constraint::spring(0, 1, 3.0, 10.0, 0.0)
+        );
+        assert_eq!(world.constraints[1], Constraint::rope(2, 1, 4.0));
+
+        let err = Scene::parse(
+            "[[particle]]\nposition = [0.0, 0.0, 0.0, 0.0]\nmass = 1.0\nradius = 1.0\n\n[[constraint]]\ntype = \"distance\"\nparticles = [0, 1]\n",
+        )
+        .unwrap_err();
+        match err {
+            SceneError::Parse { line, message, .. } => {
+                assert_eq!(line, 6);
+                assert!(message.contains("out of 1"), "{}", message);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn soft_bodies_come_after_the_particles() {
+        let scene = Scene::parse(
+            r#"
+[[soft_body]]
+center = [0.0, 10.0, 0.0]
+count = [4, 1, 5]
+spacing = 1.0
+mass = 0.1
+radius = 0.4
+stiffness = 50.0
+
+[[particle]]
+position = [0.0, 0.0, 0.0, 0.0]
+mass = 1.0
+radius = 1.0
+"#,
+        )
+        .unwrap();
+
+        let world = scene.build();
+        assert_eq!(world.particles.len(), 1 + 4 * 5);
+        assert_eq!(world.surfaces[0].triangles.len(), 2 * 3 * 4);
+        assert!(
+            world.surfaces[0]
+                .triangles
+                .iter()
+                .flatten()
+                .all(|&i| i >= 1)
+        );
+
+        let err = Scene::parse(
+            "[[soft_body]]\ncenter = [0.0, 0.0, 0.0]\ncount = [2, 2, 2]\nspacing = nan\nmass = 0.1\nradius = 0.4\nstiffness = 50.0\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("spacing"), "{}", err);
+    }
+
+    #[test]
+    fn fluid_blocks_need_sph() {
+        let scene = Scene::parse(
+            r#"
+[sph]
+smoothing_length = 0.65
+viscosity = 0.1
+
+[[fluid]]
+center = [0.0, 0.0, 0.0]
+count = [2, 3, 4]
+spacing = 0.5
+"#,
+        )
+        .unwrap();
+
+        let world = scene.build();
+        let Some(sph) = &world.sph else {
+            panic!("sph missing");
+        };
+        assert_eq!((sph.rest_density, sph.viscosity), (1000.0, 0.1));
+        assert_eq!(world.particles.len(), 24);
+        assert_eq!(world.particles[0].mass, 125.0);
+        assert_eq!(world.particles[0].radius, 0.25);
+
+        let err =
+            Scene::parse("[[fluid]]\ncenter = [0.0, 0.0, 0.0]\ncount = [2, 2, 2]\nspacing = 0.5\n")
+                .unwrap_err();
+        match err {
+            SceneError::Parse { line, message, .. } => {
+                assert_eq!(line, 1);
+                assert!(message.contains("[sph]"), "{}", message);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let err = Scene::parse(
+            "[sph]\nsmoothing_length = 0.65\n[[fluid]]\ncenter = [0.0, 0.0, 0.0]\ncount = [2, 2, 2]\nspacing = 0.5\nradius = nan\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("radius"), "{}", err);
+
+        for src in [
+            "[sph]\nsmoothing_length = inf\n",
+            "[sph]\nsmoothing_length = 0.65\nsound_speed = inf\n",
+            "[sph]\nsmoothing_length = 0.65\n[[fluid]]\ncenter = [0.0, 0.0, 0.0]\ncount = [2, 2, 2]\nspacing = 0.5\nvelocity = [nan, 0.0, 0.0]\n",
+        ] {
+            assert!(Scene::parse(src).is_err(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn step_settings_must_be_usable() {
+        for (src, message) in [
+            ("[world]\nmax_substeps = 0\n", "max_substeps"),
+            ("[world]\ndt = nan\n", "dt"),
+            ("[world]\ndt = inf\n", "dt"),
+            ("[world]\ngravity = [0.0, nan, 0.0]\n", "gravity"),
+        ] {
+            match Scene::parse(src).unwrap_err() {
+                SceneError::Parse {
+                    line, message: m, ..
+                } => {
+                    assert_eq!(line, 2, "{}", src);
+                    assert!(m.contains(message), "{}", m);
+                }
+                e => panic!("unexpected error {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn particles_must_be_finite() {
+        for src in [
+            "[[particle]]\nposition = [0.0, 0.0, 0.0, 0.0]\nmass = nan\nradius = 1.0\n",
+            "[[particle]]\nposition = [0.0, 0.0, 0.0, 0.0]\nmass = 1.0\nradius = inf\n",
+            "[[particle]]\nposition = [0.0, nan, 0.0, 0.0]\nmass = 1.0\nradius = 1.0\n",
+            "[[particle]]\nposition = [0.0, 0.0, 0.0, 0.0]\nvelocity = [nan, 0.0, 0.0]\nmass = 1.0\nradius = 1.0\n",
+            "[[cloud]]\ncount = 2\ncenter = [0.0, 0.0, 0.0]\nextent = [1.0, 1.0, 1.0]\nspeed = nan\nmass = 1.0\nradius = 0.1\n",
+        ] {
+            assert!(Scene::parse(src).is_err(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn reports_line_and_column() {
+        let err = Scene::parse("[world]\ndt = 0.01\nintegrator = \"magic\"\n").unwrap_err();
+        match err {
+            SceneError::Parse { line, column, .. } => assert_eq!((line, column), (3, 14)),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let err = Scene::parse("[[particle]]\nmass = 1.0\nradius =\n").unwrap_err();
+        match err {
+            SceneError::Parse { line, .. } => assert_eq!(line, 3),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+}
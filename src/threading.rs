@@ -1,65 +1,223 @@
 use std::{
     sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicU64, Ordering},
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use cgmath::{Vector3, Vector4};
-use crossbeam::channel::Sender;
+use crossbeam::channel::Receiver;
 
-use crate::part;
-use crate::phys::C;
-use crate::phys::get_plane_verts;
-use crate::phys::{InstanceData, PhysicsWorld};
-use crate::phys::{Particle, Plane};
-use crate::plane;
+use crate::phys::PhysicsWorld;
+use crate::scene::Scene;
+use crate::vx::{InstanceData, get_instance_data, get_surface_indices, interpolate_instances};
 
-pub enum PhysicsMessage {
-    InstanceData(Vec<InstanceData>),
+/// Fixed timestep settings for the physics loop.
+#[derive(Clone, Copy, Debug)]
+pub struct StepConfig {
+    pub dt: f32,
+    /// Most steps taken per loop iteration before the backlog is dropped.
+    pub max_substeps: u32,
 }
 
-pub fn phys_start(running: Arc<AtomicBool>, tx: Sender<PhysicsMessage>) {
-    let mut world = PhysicsWorld::new();
-
-    world.add_particle(part![
-            0.0,0.0,5.0,100.0;
-            0.0,0.0,-3000.0;
-            1;
-            10;
-            0.0,0.6,0.8
-    ]);
-
-    world.add_particle(part![
-            0.0,0.0,5.0,-100.0;
-            0.0,0.0,3000.0;
-            1;
-            10;
-            0.5,0.6,0.8
-    ]);
-
-    world.add_particle(part![
-            0.0,50.0,5.0,0.0;
-            -1500.0,0.0,0.0;
-            1;
-            10;
-            0.2,0.4,0.8
-    ]);
+impl Default for StepConfig {
+    fn default() -> Self {
+        Self {
+            dt: 1.0 / 240.0,
+            max_substeps: 8,
+        }
+    }
+}
+
+/// The two most recent physics states, for interpolated display.
+pub struct Snapshot {
+    pub prev: Vec<InstanceData>,
+    pub curr: Vec<InstanceData>,
+    /// Surface triangles over `curr`, three particle indices each.
+    pub triangles: Vec<u32>,
+    accumulator: f32,
+    dt: f32,
+    /// Simulated seconds per wall-clock second, 0 while paused.
+    rate: f32,
+    sent: Instant,
+}
+
+impl Snapshot {
+    /// Fraction of a step between `prev` and `curr` to display right now.
+    /// Keeps growing with wall-clock time after the snapshot was sent, so
+    /// frames rendered between physics steps still move smoothly.
+    pub fn alpha(&self) -> f32 {
+        let pending = self.accumulator + self.sent.elapsed().as_secs_f32() * self.rate;
+        (pending / self.dt).clamp(0.0, 1.0)
+    }
 
+    pub fn interpolated(&self) -> Vec<InstanceData> {
+        interpolate_instances(&self.prev, &self.curr, self.alpha())
+    }
+}
+
+/// Latest-wins handoff from the physics thread to the renderer.
+///
+/// Holds at most one snapshot: publishing replaces whatever the renderer has not
+/// picked up yet, so neither side ever waits on the other and memory stays
+/// bounded however far rendering lags.
+#[derive(Default)]
+pub struct SnapshotSlot {
+    latest: Mutex<Option<Snapshot>>,
+    produced: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameStats {
+    pub produced: u64,
+    pub dropped: u64,
+}
+
+impl SnapshotSlot {
+    pub fn publish(&self, snapshot: Snapshot) {
+        // The replaced snapshot is freed after the lock is released
+        let stale = self.lock().replace(snapshot);
+
+        self.produced.fetch_add(1, Ordering::Relaxed);
+        if stale.is_some() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes the newest snapshot, if one arrived since the last call.
+    pub fn take(&self) -> Option<Snapshot> {
+        self.lock().take()
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            produced: self.produced.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Snapshot>> {
+        // A panic while holding the lock cannot leave an Option half-written
+        self.latest.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Commands from the renderer to the physics thread.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhysicsCommand {
+    Pause,
+    Resume,
+    /// Advance exactly this many ticks, leaving the simulation paused.
+    Step(u32),
+    /// Simulated seconds per wall-clock second; below 1 is slow motion.
+    SetTimeScale(f32),
+    /// Rebuild the world from the scene's initial conditions.
+    Reset,
+}
+
+pub fn phys_start(
+    running: Arc<AtomicBool>,
+    slot: Arc<SnapshotSlot>,
+    commands: Receiver<PhysicsCommand>,
+    scene: Scene,
+    config: StepConfig,
+) {
+    let mut world = scene.build();
+
+    let mut prev = get_instance_data(&world.particles);
+    let mut accumulator = 0.0;
     let mut lt = Instant::now();
 
+    let mut paused = false;
+    let mut time_scale: f32 = 1.0;
+    let mut pending_steps = 0;
+    let mut next_command = None;
+
     while running.load(Ordering::SeqCst) {
-        let dt = lt.elapsed().as_secs_f32();
+        let mut dirty = false;
+
+        for command in next_command.take().into_iter().chain(commands.try_iter()) {
+            match command {
+                PhysicsCommand::Pause => paused = true,
+                PhysicsCommand::Resume => paused = false,
+                PhysicsCommand::Step(n) => {
+                    paused = true;
+                    pending_steps += n;
+                }
+                PhysicsCommand::SetTimeScale(scale) => time_scale = scale.max(0.0),
+                PhysicsCommand::Reset => {
+                    world = scene.build();
+                    prev = get_instance_data(&world.particles);
+                    accumulator = 0.0;
+                    dirty = true;
+                }
+            }
+        }
+
+        let elapsed = lt.elapsed().as_secs_f32();
         lt = Instant::now();
 
-        world.update(dt);
+        if paused {
+            for _ in 0..pending_steps {
+                world.update(config.dt);
+                dirty = true;
+            }
+            pending_steps = 0;
+
+            // Show exactly the stepped state, with the interpolation frozen
+            if dirty {
+                prev = get_instance_data(&world.particles);
+                accumulator = 0.0;
+                slot.publish(snapshot(&prev, &world, accumulator, config.dt, 0.0));
+            }
+
+            next_command = commands.recv_timeout(Duration::from_millis(50)).ok();
+            continue;
+        }
+
+        accumulator += elapsed * time_scale;
+
+        // The world only ever advances by `config.dt`, so the simulation is the
+        // same regardless of how fast this loop or the renderer runs.
+        let mut steps = 0;
+        while accumulator >= config.dt && steps < config.max_substeps {
+            prev = get_instance_data(&world.particles);
+            world.update(config.dt);
+            accumulator -= config.dt;
+            steps += 1;
+        }
 
-        // println!("freq: {} Hz", 1.0 / dt);
+        if steps == 0 && !dirty {
+            // Wait out the rest of the step, waking early for commands
+            let wait = (config.dt - accumulator) / time_scale.max(1e-3);
+            next_command = commands
+                .recv_timeout(Duration::from_secs_f32(wait.min(0.05)))
+                .ok();
+            continue;
+        }
+
+        // Too far behind to catch up: drop the backlog instead of spiralling.
+        accumulator %= config.dt;
+
+        slot.publish(snapshot(&prev, &world, accumulator, config.dt, time_scale));
+    }
+}
 
-        match tx.send(PhysicsMessage::InstanceData(world.get_instance_data())) {
-            Ok(_) => {}
-            Err(e) => println!("Failed to communicate from physics thread: {:?}", e),
-        };
+fn snapshot(
+    prev: &[InstanceData],
+    world: &PhysicsWorld,
+    accumulator: f32,
+    dt: f32,
+    rate: f32,
+) -> Snapshot {
+    Snapshot {
+        prev: prev.to_vec(),
+        curr: get_instance_data(&world.particles),
+        triangles: get_surface_indices(&world.surfaces),
+        accumulator,
+        dt,
+        rate,
+        sent: Instant::now(),
     }
 }
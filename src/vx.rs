@@ -1,13 +1,97 @@
+#[cfg(feature = "render")]
 use glium::implement_vertex;
 
+use crate::phys::Particle;
+use crate::phys::softbody::Surface;
+
 #[derive(Copy, Clone)]
 pub struct Vx {
     pub pos: [f32; 3],
     pub color: [f32; 3],
 }
 
+#[cfg(feature = "render")]
 implement_vertex!(Vx, pos, color);
 
+#[derive(Clone, Debug, Copy)]
+pub struct InstanceData {
+    pub i_pos: [f32; 3],
+    pub i_color: [f32; 3],
+    pub i_radius: f32,
+}
+#[cfg(feature = "render")]
+implement_vertex!(InstanceData, i_pos, i_color, i_radius);
+
+impl InstanceData {
+    /// Draws a mesh as it is, for geometry that is already in world space.
+    pub fn identity() -> Self {
+        InstanceData {
+            i_pos: [0.0, 0.0, 0.0],
+            i_color: [1.0, 1.0, 1.0],
+            i_radius: 1.0,
+        }
+    }
+}
+
+/// Blends two sets of instance data, `alpha = 0` giving `prev` and `alpha = 1`
+/// giving `curr`. Falls back to `curr` if the particle count changed.
+pub fn interpolate_instances(
+    prev: &[InstanceData],
+    curr: &[InstanceData],
+    alpha: f32,
+) -> Vec<InstanceData> {
+    if prev.len() != curr.len() {
+        return curr.to_vec();
+    }
+
+    let lerp = |a: f32, b: f32| a + (b - a) * alpha;
+
+    prev.iter()
+        .zip(curr)
+        .map(|(a, b)| InstanceData {
+            i_pos: [
+                lerp(a.i_pos[0], b.i_pos[0]),
+                lerp(a.i_pos[1], b.i_pos[1]),
+                lerp(a.i_pos[2], b.i_pos[2]),
+            ],
+            i_color: b.i_color,
+            i_radius: lerp(a.i_radius, b.i_radius),
+        })
+        .collect()
+}
+
+// Per-particle data for drawing each particle as an instance of the base sphere
+pub fn get_instance_data(particles: &[Particle]) -> Vec<InstanceData> {
+    particles
+        .iter()
+        .map(|p| InstanceData {
+            i_pos: [p.position[1], p.position[2], p.position[3]],
+            i_color: p.color,
+            i_radius: p.radius,
+        })
+        .collect()
+}
+
+/// Every surface triangle as three particle indices, for drawing cloth and
+/// soft bodies from the matching instance data.
+pub fn get_surface_indices(surfaces: &[Surface]) -> Vec<u32> {
+    surfaces
+        .iter()
+        .flat_map(|s| &s.triangles)
+        .flat_map(|t| t.map(|i| i as u32))
+        .collect()
+}
+
+/// Shrinks the particles under a surface to nothing, so only the surface
+/// shows.
+pub fn hide_surface_particles(instances: &mut [InstanceData], triangles: &[u32]) {
+    for &i in triangles {
+        if let Some(instance) = instances.get_mut(i as usize) {
+            instance.i_radius = 0.0;
+        }
+    }
+}
+
 // #[macro_export]
 // macro_rules! spread {
 //     ($x:expr) => {
@@ -24,7 +108,7 @@ macro_rules! vx {
 
     // Single vertex: vx![x, y => r, g, b]
     ($x:expr, $y:expr, $z:expr => $r:expr, $g:expr, $b:expr) => {
-        Vx {
+        $crate::vx::Vx {
             pos: [$x as f32, $y as f32, $z as f32],
             color: [$r as f32, $g as f32, $b as f32],
         }
@@ -33,7 +117,7 @@ macro_rules! vx {
     // multiple vertices: vx![x1, y1 => r1, g1, b1; x2, y2 => r2, g2, b2; ...]
     ($($x:expr, $y:expr, $z:expr => $r:expr, $g:expr, $b:expr);* $(;)?) => {
         vec![$(
-            vx {
+            $crate::vx::Vx {
                 pos: [$x as f32, $y as f32, $z as f32],
                 color: [$r as f32, $g as f32, $b as f32],
             }